@@ -0,0 +1,316 @@
+use std::collections::BTreeSet;
+
+use crate::cpu::{
+    decode, DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_HEIGHT, HIRES_WIDTH, PROGRAM_CAPACITY,
+    PROGRAM_START,
+};
+use crate::disasm::{opcode_category, OpcodeCategory};
+
+/// rom中能加载到内存的部分，0x200之后超出4K内存的字节不在地址空间内，分析时忽略
+pub(crate) fn loadable(bytes: &[u8]) -> &[u8] {
+    &bytes[..bytes.len().min(PROGRAM_CAPACITY)]
+}
+
+/// rom中指定地址处的操作码，地址不在rom内或只剩一个字节时返回None
+pub(crate) fn opcode_at(bytes: &[u8], address: u16) -> Option<u16> {
+    let index = (address as usize).checked_sub(PROGRAM_START as usize)?;
+    match loadable(bytes).get(index..index + 2)? {
+        &[hi, lo] => Some(decode(hi, lo).merged_opcode()),
+        _ => None,
+    }
+}
+
+/// 将rom按照2字节对齐解析为(地址, 操作码)，地址从0x200开始
+fn opcodes(bytes: &[u8]) -> impl Iterator<Item = (u16, u16)> + '_ {
+    (PROGRAM_START..)
+        .step_by(2)
+        .map_while(|address| Some((address, opcode_at(bytes, address)?)))
+}
+
+/// 扫描rom中所有的2NNN(调用子例程)指令，返回被调用地址的去重集合（升序）。
+/// rom按照2字节对齐解析为操作码，这只是一个静态扫描，数据区中恰好形如2NNN的字节也会被计入。
+pub fn find_subroutines(bytes: &[u8]) -> Vec<u16> {
+    let mut targets = BTreeSet::new();
+    for (_, opcode) in opcodes(bytes) {
+        if opcode & 0xF000 == 0x2000 {
+            targets.insert(opcode & 0x0FFF);
+        }
+    }
+    targets.into_iter().collect()
+}
+
+/// 计算rom的调用图，返回去重后的(调用方, 被调用方)边（升序）。
+/// 调用方是2NNN指令所在例程的入口地址：程序入口0x200和所有子例程入口中，
+/// 不大于该指令地址的最近一个入口。
+pub fn call_graph(bytes: &[u8]) -> Vec<(u16, u16)> {
+    let mut entries = find_subroutines(bytes);
+    entries.push(PROGRAM_START);
+    entries.sort_unstable();
+    entries.dedup();
+
+    let mut edges = BTreeSet::new();
+    for (address, opcode) in opcodes(bytes) {
+        if opcode & 0xF000 != 0x2000 {
+            continue;
+        }
+        let caller = entries
+            .iter()
+            .rev()
+            .find(|&&entry| entry <= address)
+            .copied()
+            .unwrap_or(PROGRAM_START);
+        edges.insert((caller, opcode & 0x0FFF));
+    }
+    edges.into_iter().collect()
+}
+
+/// rom静态检查发现的问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomLint {
+    pub address: u16,
+    pub message: String,
+}
+
+/// 静态扫描rom，报告可能存在的可移植性问题：
+/// 使用了本解释器尚不支持的SUPER-CHIP操作码、跳转或调用到程序区域之外、以及以过大的I执行寄存器读写。
+/// 扫描按2字节对齐进行，数据区中的字节也可能被误报。
+pub(crate) fn lint(bytes: &[u8], memory_size: usize) -> Vec<RomLint> {
+    let program_end = PROGRAM_START as usize + bytes.len();
+    let mut lints = Vec::new();
+    let mut index_register = None; // 最近一次ANNN设置的I
+
+    for (address, opcode) in opcodes(bytes) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let nnn = opcode & 0x0FFF;
+        let mut report = |message: String| lints.push(RomLint { address, message });
+
+        if opcode == 0x00FD {
+            report(format!("使用了尚不支持的SUPER-CHIP操作码 {:#06X}", opcode));
+            continue;
+        }
+
+        match opcode & 0xF000 {
+            0x1000 | 0x2000 if !(PROGRAM_START as usize..program_end).contains(&(nnn as usize)) => {
+                report(format!("跳转到程序区域之外的地址 {:#05X}", nnn));
+            }
+            0xA000 => index_register = Some(nnn as usize),
+            0xF000 if matches!(opcode & 0x00FF, 0x55 | 0x65) => {
+                if let Some(i) = index_register.filter(|i| i + x >= memory_size) {
+                    report(format!(
+                        "寄存器读写 V0～V{:X} 从 I={:#05X} 开始，超出了内存范围",
+                        x, i
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    lints
+}
+
+/// rom中一段连续区域的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Code,
+    Data,
+}
+
+/// rom中的一段连续区域，地址范围为[start, end)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub kind: RegionKind,
+}
+
+/// 从0x200开始做保守的控制流分析，返回所有可达指令的地址。
+/// 跟随无条件跳转、调用（同时继续执行调用之后的指令）和条件跳过的两个分支，
+/// 遇到返回、BNNN（目标在运行时才能确定）、无法识别的操作码或越过rom末尾时停止。
+pub(crate) fn reachable(bytes: &[u8]) -> BTreeSet<u16> {
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(address) = pending.pop() {
+        if visited.contains(&address) {
+            continue;
+        }
+        // 只有加载范围内的地址才有操作码，所以下面计算的地址最大为0x1002，不会溢出
+        let opcode = match opcode_at(bytes, address) {
+            Some(opcode) if opcode_category(opcode) != OpcodeCategory::Unknown => opcode,
+            _ => continue,
+        };
+        visited.insert(address);
+
+        let next = address + 2;
+        let nnn = opcode & 0x0FFF;
+        match opcode & 0xF000 {
+            0x0000 if opcode == 0x00EE => {}
+            0x1000 => pending.push(nnn),
+            0x2000 => pending.extend([nnn, next]),
+            0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xE000 => pending.extend([next, next + 2]),
+            0xB000 => {}
+            _ => pending.push(next),
+        }
+    }
+    visited
+}
+
+/// 将rom划分为代码区和数据区（精灵、BCD表等），避免把数据当作指令反汇编。
+/// 可达的指令由`reachable`的控制流分析得到，其余字节都视为数据
+pub fn classify_regions(bytes: &[u8]) -> Vec<Region> {
+    let code = reachable(bytes);
+    let mut regions: Vec<Region> = Vec::new();
+    for offset in 0..loadable(bytes).len() {
+        let address = PROGRAM_START + offset as u16;
+        // 指令占两个字节，第二个字节也属于代码
+        let kind = if code.contains(&address) || code.contains(&(address.wrapping_sub(1))) {
+            RegionKind::Code
+        } else {
+            RegionKind::Data
+        };
+        match regions.last_mut() {
+            Some(region) if region.kind == kind => region.end = address + 1,
+            _ => regions.push(Region {
+                start: address,
+                end: address + 1,
+                kind,
+            }),
+        }
+    }
+    regions
+}
+
+/// 屏幕的分辨率模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayMode {
+    LowRes,  // chip8的64x32
+    HighRes, // SUPER-CHIP的128x64
+}
+
+impl DisplayMode {
+    /// 该模式下屏幕的逻辑宽高
+    pub fn dimensions(&self) -> (usize, usize) {
+        match self {
+            DisplayMode::LowRes => (DISPLAY_WIDTH, DISPLAY_HEIGHT),
+            DisplayMode::HighRes => (HIRES_WIDTH, HIRES_HEIGHT),
+        }
+    }
+}
+
+/// 在运行之前猜测rom使用的分辨率，前端可以据此提前设置窗口大小。
+/// 可达代码中出现00FF（开启高分辨率）或DXY0（只在高分辨率下绘制的16x16精灵）时认为是高分辨率，
+/// 否则为低分辨率。这只是尽力而为的静态分析，控制流分析中断（如BNNN）之后的代码不会被检查
+pub fn detect_resolution(bytes: &[u8]) -> DisplayMode {
+    let hires = reachable(bytes)
+        .into_iter()
+        .filter_map(|address| opcode_at(bytes, address))
+        .any(|opcode| opcode == 0x00FF || (opcode & 0xF000 == 0xD000 && opcode & 0x000F == 0));
+    if hires {
+        DisplayMode::HighRes
+    } else {
+        DisplayMode::LowRes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_subroutines_returns_unique_call_targets() {
+        // 调用0x300、0x280，再次调用0x300
+        let rom = [0x23, 0x00, 0x22, 0x80, 0x23, 0x00, 0x12, 0x06];
+        assert_eq!(find_subroutines(&rom), [0x280, 0x300]);
+    }
+
+    #[test]
+    fn call_graph_follows_nested_calls() {
+        let rom = [
+            0x22, 0x06, // 0x200: 调用0x206
+            0x12, 0x02, // 0x202: 原地循环
+            0x00, 0x00, // 0x204
+            0x22, 0x0C, // 0x206: 调用0x20C
+            0x00, 0xEE, // 0x208: 返回
+            0x00, 0x00, // 0x20A
+            0x00, 0xEE, // 0x20C: 返回
+        ];
+        assert_eq!(call_graph(&rom), [(0x200, 0x206), (0x206, 0x20C)]);
+    }
+
+    #[test]
+    fn find_subroutines_ignores_bytes_past_memory() {
+        // 超出4K内存的部分不在地址空间内，其中的2NNN不应被计入
+        let mut rom = vec![0; 70_000];
+        rom[..2].copy_from_slice(&[0x23, 0x00]);
+        rom[PROGRAM_CAPACITY..PROGRAM_CAPACITY + 2].copy_from_slice(&[0x24, 0x00]);
+        assert_eq!(find_subroutines(&rom), [0x300]);
+    }
+
+    #[test]
+    fn classify_regions_marks_sprite_table_as_data() {
+        let rom = [
+            0xA2, 0x06, // 0x200: I = 0x206
+            0xD0, 0x13, // 0x202: 绘制3行
+            0x12, 0x04, // 0x204: 原地循环
+            0xF0, 0x90, 0xF0, // 0x206: 精灵
+        ];
+        assert_eq!(
+            classify_regions(&rom),
+            [
+                Region {
+                    start: 0x200,
+                    end: 0x206,
+                    kind: RegionKind::Code,
+                },
+                Region {
+                    start: 0x206,
+                    end: 0x209,
+                    kind: RegionKind::Data,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reachable_follows_skips_calls_and_jumps() {
+        let rom = [
+            0x30, 0x01, // 0x200: if V0 == 1 跳过下一条
+            0x12, 0x08, // 0x202: 跳转到0x208
+            0x22, 0x0C, // 0x204: 调用0x20C
+            0x12, 0x06, // 0x206: 原地循环
+            0x12, 0x08, // 0x208: 原地循环
+            0x60, 0x00, // 0x20A: 不可达
+            0x00, 0xEE, // 0x20C: 返回
+            0x61, 0x00, // 0x20E: 不可达
+        ];
+        assert_eq!(
+            reachable(&rom).into_iter().collect::<Vec<_>>(),
+            [0x200, 0x202, 0x204, 0x206, 0x208, 0x20C]
+        );
+    }
+
+    #[test]
+    fn classify_regions_stops_at_end_of_memory() {
+        // 全是0000（SYS），控制流一直走到内存末尾
+        assert_eq!(
+            classify_regions(&vec![0; 70_000]),
+            [Region {
+                start: 0x200,
+                end: 0x1000,
+                kind: RegionKind::Code,
+            }]
+        );
+    }
+
+    #[test]
+    fn detect_resolution_finds_00ff() {
+        // 00E0；00FF；原地循环
+        let hires = [0x00, 0xE0, 0x00, 0xFF, 0x12, 0x04];
+        assert_eq!(detect_resolution(&hires), DisplayMode::HighRes);
+        assert_eq!(DisplayMode::HighRes.dimensions(), (128, 64));
+
+        // 00FF在不可达的位置
+        let lores = [0x00, 0xE0, 0x12, 0x02, 0x00, 0xFF];
+        assert_eq!(detect_resolution(&lores), DisplayMode::LowRes);
+    }
+}
@@ -0,0 +1,32 @@
+/// 蜂鸣器的音频输出。sound_timer从0变为非0时调用`start_beep`，回到0时调用`stop_beep`，
+/// 前端可以实现这个trait来驱动真实的音频设备（例如SDL的方波发生器）
+pub trait AudioSink {
+    fn start_beep(&mut self);
+    fn stop_beep(&mut self);
+}
+
+/// 不输出任何声音的默认实现
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn start_beep(&mut self) {}
+    fn stop_beep(&mut self) {}
+}
+
+/// 将回调函数适配为音频输出，蜂鸣器开始时以`true`调用，停止时以`false`调用
+pub(crate) struct CallbackAudio(pub(crate) Box<dyn FnMut(bool) + Send>);
+
+impl AudioSink for CallbackAudio {
+    fn start_beep(&mut self) {
+        (self.0)(true)
+    }
+
+    fn stop_beep(&mut self) {
+        (self.0)(false)
+    }
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn null_audio() -> Box<dyn AudioSink + Send> {
+    Box::new(NullAudio)
+}
@@ -1,13 +1,37 @@
-use std::{fs::File, io::Read, path::Path};
+use std::{collections::HashSet, fs::File, io::Read, ops::Range, path::Path};
 
 use anyhow::anyhow;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 
-pub const SCREEN_WIDTH: usize = 640; // 宽
-pub const SCREEN_HEIGHT: usize = 320; // 高
+use crate::analysis::{self, RomLint};
+use crate::audio::{AudioSink, CallbackAudio, NullAudio};
+use crate::disasm::{opcode_category, OpcodeCategory};
+use crate::error::Chip8Error;
+use crate::input::KEYPAD_LAYOUT;
+use crate::quirks::Quirks;
+
+pub const DISPLAY_WIDTH: usize = 64; // chip8屏幕的逻辑宽度
+pub const DISPLAY_HEIGHT: usize = 32; // chip8屏幕的逻辑高度
+pub const HIRES_WIDTH: usize = 128; // SUPER-CHIP高分辨率模式的逻辑宽度
+pub const HIRES_HEIGHT: usize = 64; // SUPER-CHIP高分辨率模式的逻辑高度
+const DISPLAY_SCALE: usize = 10; // 渲染时每个逻辑像素默认放大的倍数
+pub const SCREEN_WIDTH: usize = DISPLAY_WIDTH * DISPLAY_SCALE; // 窗口宽
+pub const SCREEN_HEIGHT: usize = DISPLAY_HEIGHT * DISPLAY_SCALE; // 窗口高
 const MEMORY_SIZE: usize = 4096; // 内存大小 4k
 const REGISTER_SIZE: usize = 16; // 数量 16
-const STACK_SIZE: usize = 16; // 堆栈层级
+const STACK_SIZE: usize = 16; // 默认的堆栈层级
+const MAX_STACK_LIMIT: usize = 256; // 可配置的堆栈层级上限
 const KEYPAD_SIZE: usize = 16; // 键数量
+const RPL_SIZE: usize = 8; // SUPER-CHIP的RPL用户标志寄存器数量
+pub(crate) const PROGRAM_START: u16 = 0x200; // 程序在内存中的起始地址
+pub(crate) const PROGRAM_CAPACITY: usize = MEMORY_SIZE - PROGRAM_START as usize; // 0x200之后能存放程序的字节数
+const RNG_STATE_SIZE: usize = 32 + 8 + 16; // 随机数生成器状态的字节数：种子、流编号、字位置
+#[cfg(feature = "serde")]
+const SAVE_STATE_MAGIC: &[u8; 4] = b"C8SS"; // 存档文件头的标识
+#[cfg(feature = "serde")]
+const SAVE_STATE_VERSION: u16 = 1; // 存档格式的版本，存档的字段变化时需要增加
+const ADDRESS_MASK: u16 = 0x0FFF; // 12位地址空间，pc、I、跳转地址和内存访问超过0xFFF时回绕到0x000
 
 // chip8字体集
 const FONTSET: [u8; 80] = [
@@ -29,15 +53,32 @@ const FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// SUPER-CHIP的8x10大字体，只有数字0-9，紧跟在FONTSET之后存放。
+// 内存布局：0x000-0x04F为FONTSET，0x050-0x0B3为BIG_FONTSET，都在0x200的程序区域之前
+const BIG_FONTSET_START: usize = FONTSET.len();
+const BIG_FONTSET: [u8; 100] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+];
+
 /// OpCode是由两个字节组成的操作码，我们从mem中获取到的mem[i]和mem[i+1]组成一个完整的OpCode。
 /// 将这两个字节的操作码拆分，例如OpCode为0xA000，拆分后我们可以获得(0xA, 0x0, 0x0, 0x0)，
 /// 这个数据形式方便我们通过match匹配
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OpCode {
-    first: u8,
-    second: u8,
-    third: u8,
-    fourth: u8,
+    pub(crate) first: u8,
+    pub(crate) second: u8,
+    pub(crate) third: u8,
+    pub(crate) fourth: u8,
 }
 
 impl OpCode {
@@ -50,28 +91,152 @@ impl OpCode {
     }
 }
 
+/// 0开头的操作码对应的指令，名称与反汇编的助记符相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SystemOp {
+    Cls,  // 00E0
+    Ret,  // 00EE
+    Scd,  // 00CN
+    Scr,  // 00FB
+    Scl,  // 00FC
+    Low,  // 00FE
+    High, // 00FF
+    Sys,  // 其余的0NNN，按空操作处理
+}
+
+/// 区分0开头的操作码，process_opcode据此分发，skip_nop_0nnn据此判断哪些指令可以跳过，
+/// 两处共用这一个判断，新增0开头的指令时只需要修改这里
+fn system_op(opcode: &OpCode) -> SystemOp {
+    match (opcode.second, opcode.third, opcode.fourth) {
+        (0, 0xE, 0) => SystemOp::Cls,
+        (0, 0xE, 0xE) => SystemOp::Ret,
+        (0, 0xC, _) => SystemOp::Scd,
+        (0, 0xF, 0xB) => SystemOp::Scr,
+        (0, 0xF, 0xC) => SystemOp::Scl,
+        (0, 0xF, 0xE) => SystemOp::Low,
+        (0, 0xF, 0xF) => SystemOp::High,
+        _ => SystemOp::Sys,
+    }
+}
+
+/// 是否为process_opcode中按空操作处理的0NNN
+fn is_nop_0nnn(opcode: &OpCode) -> bool {
+    opcode.first == 0 && system_op(opcode) == SystemOp::Sys
+}
+
+/// 将内存中相邻的两个字节解码为OpCode，CHIP-8的操作码是大端序的，即`hi << 8 | lo`
+pub(crate) fn decode(hi: u8, lo: u8) -> OpCode {
+    OpCode {
+        first: hi >> 4,
+        second: hi & 0x0F,
+        third: lo >> 4,
+        fourth: lo & 0x0F,
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Emulator {
-    opcode: OpCode,            // 操作码
+    opcode: OpCode, // 操作码
+    cycles: u64,    // 已执行的指令数
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::bytes"))]
     memory: [u8; MEMORY_SIZE], // 内存
 
     registers: [u8; REGISTER_SIZE], //  V0～VE
     index_register: u16,            // 索引（i）和程序计数器（pc），从0x000到0xFFF
     program_counter: u16,
 
-    pub gfx: [[u8; SCREEN_WIDTH]; SCREEN_HEIGHT], // 屏幕
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::bytes_2d"))]
+    gfx: [[u8; HIRES_WIDTH]; HIRES_HEIGHT], // 屏幕，按逻辑像素存储，缩放由渲染器负责；低分辨率时只使用左上角64x32
+    hires: bool,          // 是否处于SUPER-CHIP高分辨率模式
+    display_scale: usize, // 推荐给前端的像素放大倍数
+    draw_flag: bool,      // 屏幕内容是否发生了变化
 
     // 两个60hz的定时，当设置在0以上时，它们会倒数到0，每当sound_timer达到0时，系统的蜂鸣器会发出声音
     delay_timer: u8,
     sound_timer: u8,
 
-    stack: [u16; STACK_SIZE], // 系统堆栈
-    stack_pointer: usize,     // 堆栈指针
+    rpl: [u8; RPL_SIZE], // SUPER-CHIP的RPL用户标志，由FX75/FX85读写，reset时不会清空
+
+    stack: Vec<u16>,    // 系统堆栈，长度即堆栈指针
+    stack_limit: usize, // 堆栈的最大层级
 
     pub keypad: [bool; KEYPAD_SIZE], // 基于hex的键盘，长度为0x0～0xF，记录键盘状态
+
+    enforce_alignment: bool, // 是否检查跳转地址的2字节对齐
+    skip_nop_0nnn: bool,     // 是否一次跳过连续的0NNN空操作
+
+    rom_len: usize,    // 已加载rom的长度
+    ran_off_end: bool, // pc是否曾经越过rom的末尾
+
+    rng: ChaCha12Rng,            // CXNN使用的随机数生成器
+    reseed_on_load: Option<u64>, // 加载rom时使用的随机数种子
+
+    quirks: Quirks, // 有歧义的操作码的行为选择
+
+    // 调试断点，属于调试器的配置，不会保存到存档中
+    #[cfg_attr(feature = "serde", serde(skip))]
+    breakpoints: HashSet<u16>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stopped_at: Option<u16>, // 上一次step停在的断点，再次step时会执行该处的指令
+
+    #[cfg_attr(feature = "serde", serde(skip, default = "crate::audio::null_audio"))]
+    audio: Box<dyn AudioSink + Send>, // 蜂鸣器的音频输出
+    #[cfg_attr(feature = "serde", serde(skip))]
+    trace_hook: Option<Box<dyn FnMut(u16, u16) + Send>>, // 每条指令执行前调用，参数为(pc, opcode)
+}
+
+/// CPU状态的快照，供调试器显示寄存器面板
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuState {
+    pub registers: [u8; REGISTER_SIZE],
+    pub index_register: u16,
+    pub program_counter: u16,
+    pub stack_pointer: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+/// `step`的执行结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// 执行了一条指令
+    Executed(OpCode),
+    /// pc到达断点，指令没有被执行，再次调用`step`会执行这条指令
+    Breakpoint(u16),
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Emulator {
     pub fn new() -> Self {
+        Self::with_rng(ChaCha12Rng::from_entropy())
+    }
+
+    /// 使用固定的随机数种子创建模拟器，相同种子的CXNN会产生相同的随机数序列，便于测试和回放
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_rng(ChaCha12Rng::seed_from_u64(seed))
+    }
+
+    /// 使用指定的quirks创建模拟器，随机数种子来自系统熵源。
+    /// 需要固定种子时先用`with_seed`创建，再调用`set_quirks`
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        let mut chip8 = Self::new();
+        chip8.set_quirks(quirks);
+        chip8
+    }
+
+    /// 严格按照规范运行的模拟器，即`with_quirks(Quirks::cosmac_vip())`的简写，可以作为检查rom兼容性的基准。
+    /// 双重pc递增、5XY0、8XYE、7XNN回绕和FX0A等修复在所有模式下都是生效的，
+    /// 与`new`的区别只在于8XY6/8XYE使用VY、FX55/FX65会增加I这两个COSMAC VIP的行为
+    pub fn strict() -> Self {
+        Self::with_quirks(Quirks::cosmac_vip())
+    }
+
+    fn with_rng(rng: ChaCha12Rng) -> Self {
         let mut chip8 = Emulator {
             opcode: OpCode {
                 first: 0,
@@ -79,63 +244,579 @@ impl Emulator {
                 third: 0,
                 fourth: 0,
             },
+            cycles: 0,
             memory: [0; MEMORY_SIZE],
             registers: [0; REGISTER_SIZE],
+            rpl: [0; RPL_SIZE],
             index_register: 0,
-            program_counter: 0x200, // chip8解释器本身占用了机器上内存空间的前512个字节，由于这个原因，为原始系统编写的大多数程序都是从内存位置512（0x200）开始的
-            gfx: [[0; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            program_counter: PROGRAM_START, // chip8解释器本身占用了机器上内存空间的前512个字节，由于这个原因，为原始系统编写的大多数程序都是从内存位置512（0x200）开始的
+            gfx: [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            hires: false,
+            display_scale: DISPLAY_SCALE,
+            draw_flag: false,
             delay_timer: 0,
             sound_timer: 0,
-            stack: [0; STACK_SIZE],
-            stack_pointer: 0,
+            stack: Vec::with_capacity(STACK_SIZE),
+            stack_limit: STACK_SIZE,
             keypad: [false; KEYPAD_SIZE],
+            enforce_alignment: false,
+            skip_nop_0nnn: false,
+            rom_len: 0,
+            ran_off_end: false,
+            rng,
+            reseed_on_load: None,
+            quirks: Quirks::default(),
+            breakpoints: HashSet::new(),
+            stopped_at: None,
+            audio: Box::new(NullAudio),
+            trace_hook: None,
         };
-        // 加载字体集到内存前80个字节
-        for (index, value) in FONTSET.into_iter().enumerate() {
-            chip8.memory[index] = value;
-        }
+        chip8.load_fontset();
         chip8
     }
 
-    /// 将程序加载到内存中
+    /// 加载字体集和大字体集到内存开头
+    fn load_fontset(&mut self) {
+        self.memory[..FONTSET.len()].copy_from_slice(&FONTSET);
+        self.memory[BIG_FONTSET_START..BIG_FONTSET_START + BIG_FONTSET.len()]
+            .copy_from_slice(&BIG_FONTSET);
+    }
+
+    /// 重置模拟器以重新开始运行，已加载到0x200之后的rom会被保留。
+    /// 寄存器、堆栈、定时器、键盘和屏幕都会被清空，pc指向0x200，并重新加载字体集
+    pub fn reset(&mut self) {
+        self.memory[..PROGRAM_START as usize].fill(0);
+        self.load_fontset();
+        self.registers = [0; REGISTER_SIZE];
+        self.index_register = 0;
+        self.program_counter = PROGRAM_START;
+        self.gfx = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        self.hires = false;
+        self.draw_flag = true;
+        self.delay_timer = 0;
+        self.set_sound_timer(0);
+        self.stack.clear();
+        self.keypad = [false; KEYPAD_SIZE];
+        self.ran_off_end = false;
+    }
+
+    /// 导出随机数生成器的完整状态，可以通过`set_rng_state`恢复，用于回放中精确地检查点和还原随机数序列。
+    /// 状态由种子(32字节)、流编号(8字节)和字位置(16字节)组成，均为小端序
+    pub fn rng_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(RNG_STATE_SIZE);
+        state.extend_from_slice(&self.rng.get_seed());
+        state.extend_from_slice(&self.rng.get_stream().to_le_bytes());
+        state.extend_from_slice(&self.rng.get_word_pos().to_le_bytes());
+        state
+    }
+
+    /// 恢复由`rng_state`导出的随机数生成器状态
+    pub fn set_rng_state(&mut self, state: &[u8]) -> anyhow::Result<()> {
+        if state.len() != RNG_STATE_SIZE {
+            return Err(anyhow!(
+                "随机数状态长度错误: {} 字节，应为 {} 字节",
+                state.len(),
+                RNG_STATE_SIZE
+            ));
+        }
+        let (seed, rest) = state.split_at(32);
+        let (stream, word_pos) = rest.split_at(8);
+        let mut rng = ChaCha12Rng::from_seed(seed.try_into()?);
+        rng.set_stream(u64::from_le_bytes(stream.try_into()?));
+        rng.set_word_pos(u128::from_le_bytes(word_pos.try_into()?));
+        self.rng = rng;
+        Ok(())
+    }
+
+    /// 开启后，1NNN、2NNN、BNNN跳转到奇数地址时返回`Chip8Error::MisalignedJump`。
+    /// CHIP-8的指令都是2字节对齐的，跳转到奇数地址通常意味着rom有bug，
+    /// 但也有少数rom会故意这样做，所以默认关闭。
+    pub fn set_enforce_alignment(&mut self, enforce: bool) {
+        self.enforce_alignment = enforce;
+    }
+
+    /// 开启后，执行到0NNN空操作时会一次跳过后面连续的所有0NNN，用于快进或性能测试。
+    /// 跳过的指令不会触发跟踪回调，遇到断点时会停在断点处，除此之外结果与逐条执行相同，默认关闭。
+    pub fn set_skip_nop_0nnn(&mut self, skip: bool) {
+        self.skip_nop_0nnn = skip;
+    }
+
+    /// 有歧义的操作码当前使用的行为
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// 切换有歧义的操作码的行为，从下一条指令开始生效
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// 获取当前CPU状态的快照
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers,
+            index_register: self.index_register,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack.len(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    /// 寄存器V0～VF
+    pub fn registers(&self) -> &[u8; REGISTER_SIZE] {
+        &self.registers
+    }
+
+    /// 索引寄存器I
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    /// 程序计数器pc
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    /// 堆栈指针
+    pub fn stack_pointer(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// 设置堆栈的最大层级，默认为16，最大为256。
+    /// 少数变种或自制rom需要比16层更深的嵌套调用
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit.min(MAX_STACK_LIMIT);
+    }
+
+    /// 定时器的值(delay_timer, sound_timer)
+    pub fn timers(&self) -> (u8, u8) {
+        (self.delay_timer, self.sound_timer)
+    }
+
+    /// pc处下一条将要执行的指令的分类
+    pub fn current_opcode_category(&self) -> OpcodeCategory {
+        opcode_category(self.peek_opcode())
+    }
+
+    /// 按行返回屏幕的逻辑像素，每个元素为0或1。
+    /// 只包含当前分辨率下`dimensions()`大小的区域，低分辨率为32行64列，高分辨率为64行128列
+    pub fn framebuffer(&self) -> impl ExactSizeIterator<Item = &[u8]> + '_ {
+        let (width, height) = self.dimensions();
+        self.gfx[..height].iter().map(move |row| &row[..width])
+    }
+
+    /// 是否处于SUPER-CHIP高分辨率（128x64）模式，由00FF开启，00FE关闭
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /// 当前分辨率下屏幕的逻辑宽高，低分辨率为(64, 32)，高分辨率为(128, 64)，前端可以据此调整渲染区域
+    pub fn dimensions(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        }
+    }
+
+    /// 设置推荐给前端的像素放大倍数，默认为10
+    pub fn set_display_scale(&mut self, scale: usize) {
+        self.display_scale = scale;
+    }
+
+    /// 按放大倍数缩放后的屏幕宽高，前端可以据此设置窗口大小，例如低分辨率、倍数为10时为(640, 320)
+    pub fn scaled_dimensions(&self) -> (usize, usize) {
+        let (width, height) = self.dimensions();
+        (width * self.display_scale, height * self.display_scale)
+    }
+
+    /// 查询坐标(x, y)的像素是否点亮，坐标超出当前分辨率的屏幕时返回false
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let (width, height) = self.dimensions();
+        x < width && y < height && self.gfx[y][x] != 0
+    }
+
+    /// 检查在(x, y)绘制精灵是否会与屏幕上已点亮的像素发生碰撞，不会修改屏幕。
+    /// 坐标回绕规则与DXYN相同
+    pub fn would_collide(&self, x: u8, y: u8, sprite: &[u8]) -> bool {
+        let (width, height) = self.dimensions();
+        sprite.iter().enumerate().any(|(j, row)| {
+            (0..8).any(|i| {
+                let py = (y as usize + j) % height;
+                let px = (x as usize + i) % width;
+                (row & (0x80 >> i)) != 0x00 && self.gfx[py][px] == 0x01
+            })
+        })
+    }
+
+    /// 返回自上次调用以来屏幕是否需要重绘，并清除该标志。
+    /// 00E0和DXYN会设置该标志，前端可以据此跳过没有变化的帧
+    pub fn take_draw_flag(&mut self) -> bool {
+        std::mem::take(&mut self.draw_flag)
+    }
+
+    /// 松开所有按键。窗口失去焦点时按键的松开事件可能丢失，前端应在失去焦点时调用，避免按键卡住。
+    /// 使用`KeyRepeat`自动连发时应改为调用`KeyRepeat::release_all`，否则被连发临时松开的按键会在下一帧重新按下
+    pub fn release_all_keys(&mut self) {
+        self.keypad = [false; KEYPAD_SIZE];
+    }
+
+    /// 按照键盘布局渲染当前按键状态，按下的键用`[ ]`标出，用于调试时显示输入状态
+    pub fn keypad_string(&self) -> String {
+        KEYPAD_LAYOUT
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&key| {
+                        if self.keypad[key] {
+                            format!("[{:X}]", key)
+                        } else {
+                            format!(" {:X} ", key)
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// 将模拟器的完整状态（内存、寄存器、堆栈、屏幕、定时器、键盘等）保存为二进制数据。
+    /// 数据以4字节的标识和2字节的版本号（小端序）开头，之后是bincode编码的状态
+    #[cfg(feature = "serde")]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut data = SAVE_STATE_MAGIC.to_vec();
+        data.extend_from_slice(&SAVE_STATE_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut data, self).expect("模拟器状态总是可以序列化");
+        data
+    }
+
+    /// 从`save_state`保存的数据中恢复模拟器状态。
+    /// 标识或版本不匹配、数据无法解码，或者状态本身不合法（如pc超出地址空间）时返回错误，当前状态保持不变
+    #[cfg(feature = "serde")]
+    pub fn load_state(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let header_len = SAVE_STATE_MAGIC.len() + 2;
+        if data.len() < header_len || &data[..SAVE_STATE_MAGIC.len()] != SAVE_STATE_MAGIC {
+            return Err(anyhow!("不是有效的存档数据"));
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != SAVE_STATE_VERSION {
+            return Err(anyhow!(
+                "存档版本不匹配: 存档为第 {} 版，当前只支持第 {} 版",
+                version,
+                SAVE_STATE_VERSION
+            ));
+        }
+        let state: Emulator = match bincode::deserialize(&data[header_len..]) {
+            Ok(state) => state,
+            Err(e) => return Err(anyhow!("读取存档异常: {}", e)),
+        };
+        state.validate()?;
+        let sound_timer = state.sound_timer;
+        let previous = std::mem::replace(self, state);
+        // 保留调试器和音频的配置，并让蜂鸣器的状态与存档一致
+        self.breakpoints = previous.breakpoints;
+        self.audio = previous.audio;
+        self.trace_hook = previous.trace_hook;
+        self.sound_timer = previous.sound_timer;
+        self.set_sound_timer(sound_timer);
+        Ok(())
+    }
+
+    /// 检查从存档中读取的状态，避免不合法的值在之后执行时导致panic
+    #[cfg(feature = "serde")]
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.program_counter > ADDRESS_MASK {
+            return Err(anyhow!(
+                "存档中的pc超出地址空间: {:#06X}",
+                self.program_counter
+            ));
+        }
+        if self.index_register > ADDRESS_MASK {
+            return Err(anyhow!(
+                "存档中的I超出地址空间: {:#06X}",
+                self.index_register
+            ));
+        }
+        if self.rom_len > PROGRAM_CAPACITY {
+            return Err(anyhow!("存档中的rom长度过大: {} 字节", self.rom_len));
+        }
+        if self.stack_limit > MAX_STACK_LIMIT || self.stack.len() > self.stack_limit {
+            return Err(anyhow!(
+                "存档中的堆栈不合法: {} 层，上限为 {} 层",
+                self.stack.len(),
+                self.stack_limit
+            ));
+        }
+        if let Some(address) = self.stack.iter().find(|&&address| address > ADDRESS_MASK) {
+            return Err(anyhow!("存档中的返回地址超出地址空间: {:#06X}", address));
+        }
+        Ok(())
+    }
+
+    /// 从文件中读取程序并加载到内存中
     pub fn load_rom<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
-        let file = match File::open(path) {
+        let mut file = match File::open(path) {
             Ok(f) => f,
-            Err(e) => return Err(anyhow!("打开文件异常: {}", e.to_string())),
+            Err(e) => return Err(anyhow!("打开文件异常: {}", e)),
         };
-        for (index, value) in file.bytes().enumerate() {
-            match value {
-                Ok(v) => self.memory[index + self.program_counter as usize] = v,
-                Err(e) => return Err(anyhow!("读取到错误的字节: {}", e.to_string())),
-            }
+        let mut rom = Vec::new();
+        if let Err(e) = file.read_to_end(&mut rom) {
+            return Err(anyhow!("读取到错误的字节: {}", e));
+        }
+        self.load_rom_bytes(&rom)
+    }
+
+    /// 将内存中的程序加载到内存中，程序从0x200开始存放，最多只能占用0x200之后的3584个字节。
+    /// 0x200之后的内存会先被清零，避免之前加载的更长的rom残留在新rom之后。
+    /// 适用于`include_bytes!`嵌入的rom或没有文件系统的环境（如WASM）
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> anyhow::Result<()> {
+        if rom.len() > PROGRAM_CAPACITY {
+            return Err(anyhow!(
+                "ROM过大: {} 字节，可用空间只有 {} 字节",
+                rom.len(),
+                PROGRAM_CAPACITY
+            ));
+        }
+        let start = PROGRAM_START as usize;
+        self.memory[start..].fill(0);
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        self.rom_len = rom.len();
+        self.ran_off_end = false;
+        if let Some(seed) = self.reseed_on_load {
+            self.rng = ChaCha12Rng::seed_from_u64(seed);
         }
         Ok(())
     }
 
+    /// 设置后，每次加载rom都会用这个种子重置随机数生成器，同一个rom和种子的每次运行从一开始就完全相同。
+    /// 默认为None，加载rom时不改变随机数生成器
+    pub fn set_reseed_on_load(&mut self, seed: Option<u64>) {
+        self.reseed_on_load = seed;
+    }
+
+    /// 静态检查已加载的rom，报告使用不支持的操作码、跳转到程序区域之外等可移植性问题
+    pub fn lint_rom(&self) -> Vec<RomLint> {
+        analysis::lint(self.program(), MEMORY_SIZE)
+    }
+
+    /// 从0x200开始对已加载的rom做保守的控制流分析，返回所有可达指令的地址，
+    /// 规则与`classify_regions`相同
+    pub fn reachable_addresses(&self) -> HashSet<u16> {
+        analysis::reachable(self.program()).into_iter().collect()
+    }
+
+    /// 已加载到内存中的rom
+    fn program(&self) -> &[u8] {
+        let start = PROGRAM_START as usize;
+        &self.memory[start..start + self.rom_len]
+    }
+
+    /// 已执行的指令数（`skip_nop_0nnn`一次跳过的每条0NNN也各计一次），
+    /// 配合墙钟时间可以计算实际的指令频率
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// 将已执行的指令数清零
+    pub fn reset_cycles(&mut self) {
+        self.cycles = 0;
+    }
+
+    /// pc是否曾经越过已加载rom的末尾。
+    /// 没有以跳转结尾的rom会继续执行后面被置零的内存（解码为0x0000），前端可以据此提示程序已经跑飞
+    pub fn ran_off_end(&self) -> bool {
+        self.ran_off_end
+    }
+
+    /// 与旧版本一样忽略执行错误，需要处理错误时请使用`cycle`
+    #[deprecated(note = "请使用`cycle`")]
     pub fn emulator_cycle(&mut self) {
+        let _ = self.cycle();
+    }
+
+    /// 执行一个周期：执行一条指令并更新一次定时器，指令出错时不会更新定时器。
+    /// 这只适合简单的前端，指令会以60Hz运行；需要准确时序时请使用`step_cpu`和`tick_timers`
+    pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+        self.step_cpu()?;
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// 获取并执行一条指令，不会更新定时器。
+    /// CPU的频率（通常为500～1000Hz）和60Hz的定时器是相互独立的，推荐的主循环是每帧（1/60秒）
+    /// 先调用N次`step_cpu`，再调用一次`tick_timers`，例如600Hz时每帧执行10条指令。
+    pub fn step_cpu(&mut self) -> Result<(), Chip8Error> {
+        self.execute().map(|_| ())
+    }
+
+    /// 执行一帧：调用`cycles`次`step_cpu`，再调用一次`tick_timers`，
+    /// 即推荐主循环中每帧的内容，适合测试、截图对比等无界面的场景。指令出错时立即返回
+    pub fn run_frame(&mut self, cycles: usize) -> Result<(), Chip8Error> {
+        for _ in 0..cycles {
+            self.step_cpu()?;
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// 连续执行n条指令（与`step_cpu`相同，不会更新定时器），用于无界面地运行rom。
+    /// 需要定时器时请使用`run_frame`，指令出错时立即返回
+    pub fn run_cycles(&mut self, n: usize) -> Result<(), Chip8Error> {
+        for _ in 0..n {
+            self.step_cpu()?;
+        }
+        Ok(())
+    }
+
+    /// 连续执行指令（与`step_cpu`相同，不会更新定时器），直到`pred`成立。每条指令执行前检查`pred`，
+    /// 成立时返回true，执行了`max`条指令后仍不成立时返回false，指令出错时立即返回错误
+    pub fn run_until<F: Fn(&Emulator) -> bool>(
+        &mut self,
+        pred: F,
+        max: usize,
+    ) -> Result<bool, Chip8Error> {
+        for _ in 0..max {
+            if pred(self) {
+                return Ok(true);
+            }
+            self.step_cpu()?;
+        }
+        Ok(pred(self))
+    }
+
+    /// 单步执行一条指令（不会更新定时器），供调试器逐条驱动执行。
+    /// 如果pc处有断点，第一次调用会返回`StepResult::Breakpoint`而不执行指令，再次调用才会执行
+    pub fn step(&mut self) -> Result<StepResult, Chip8Error> {
+        let pc = self.program_counter;
+        if self.breakpoints.contains(&pc) && self.stopped_at != Some(pc) {
+            self.stopped_at = Some(pc);
+            return Ok(StepResult::Breakpoint(pc));
+        }
+        self.stopped_at = None;
+        self.execute().map(StepResult::Executed)
+    }
+
+    /// 一直执行指令，直到pc离开`range`，用于调试时跑完一个函数。
+    /// pc离开范围时返回true，执行了`max_cycles`条指令后仍在范围内时返回false。
+    /// 与`step_cpu`一样不检查断点，也不更新定时器
+    pub fn run_until_pc_leaves(
+        &mut self,
+        range: Range<u16>,
+        max_cycles: usize,
+    ) -> Result<bool, Chip8Error> {
+        for _ in 0..max_cycles {
+            if !range.contains(&self.program_counter) {
+                return Ok(true);
+            }
+            self.execute()?;
+        }
+        Ok(!range.contains(&self.program_counter))
+    }
+
+    /// 执行一条指令（与`step_cpu`相同），返回操作码和可读的变化描述，例如`V3: 0x01 → 0x06`。
+    /// 描述覆盖寄存器、内存、PC、I和定时器，与反汇编结合可以得到紧凑的调试跟踪
+    pub fn step_with_diff(&mut self) -> Result<(u16, Vec<String>), Chip8Error> {
+        let registers = self.registers;
+        let memory = self.memory;
+        let (pc, index) = (self.program_counter, self.index_register);
+        let (delay, sound) = (self.delay_timer, self.sound_timer);
+
+        let opcode = self.execute()?.merged_opcode();
+
+        let mut diff = Vec::new();
+        for (i, (old, new)) in registers.iter().zip(self.registers.iter()).enumerate() {
+            if old != new {
+                diff.push(format!("V{:X}: {:#04X} → {:#04X}", i, old, new));
+            }
+        }
+        for (address, (old, new)) in memory.iter().zip(self.memory.iter()).enumerate() {
+            if old != new {
+                diff.push(format!("[{:#05X}]: {:#04X} → {:#04X}", address, old, new));
+            }
+        }
+        if pc != self.program_counter {
+            diff.push(format!("PC: {:#05X} → {:#05X}", pc, self.program_counter));
+        }
+        if index != self.index_register {
+            diff.push(format!("I: {:#05X} → {:#05X}", index, self.index_register));
+        }
+        if delay != self.delay_timer {
+            diff.push(format!("DT: {} → {}", delay, self.delay_timer));
+        }
+        if sound != self.sound_timer {
+            diff.push(format!("ST: {} → {}", sound, self.sound_timer));
+        }
+        Ok((opcode, diff))
+    }
+
+    /// 设置执行跟踪的回调，每条指令执行前以(指令地址, 操作码)调用，传入None取消跟踪。
+    /// 没有设置回调时不会产生额外开销
+    pub fn set_trace_hook(&mut self, hook: Option<Box<dyn FnMut(u16, u16) + Send>>) {
+        self.trace_hook = hook;
+    }
+
+    /// 在地址上设置断点
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    /// 移除地址上的断点
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    /// 获取并执行一条指令，返回执行的操作码
+    fn execute(&mut self) -> Result<OpCode, Chip8Error> {
         // 获取操作码
         self.fetch_opcode();
         // 执行操作码
-        self.process_opcode();
-        // 更新定时器
-        self.update_timer()
+        self.process_opcode()?;
+        let executed = self.opcode;
+        if self.skip_nop_0nnn && is_nop_0nnn(&executed) {
+            // 最多绕内存一圈，避免全是0的内存导致死循环；遇到断点时停下，让调试器能停在断点处
+            for _ in 0..MEMORY_SIZE / 2 {
+                if !is_nop_0nnn(&self.read_opcode_at(self.program_counter))
+                    || self.breakpoints.contains(&self.program_counter)
+                {
+                    break;
+                }
+                self.fetch_opcode();
+            }
+            // 返回和记录的是实际执行的那条指令，而不是最后跳过的0NNN
+            self.opcode = executed;
+        }
+        Ok(executed)
+    }
+
+    /// 读取pc处的操作码，不移动pc
+    fn peek_opcode(&self) -> u16 {
+        self.read_opcode_at(self.program_counter).merged_opcode()
+    }
+
+    /// 解码指定地址处的两个字节，地址超过0xFFF时与`read_mem`一样回绕
+    fn read_opcode_at(&self, address: u16) -> OpCode {
+        decode(
+            self.read_mem(address),
+            self.read_mem(address.wrapping_add(1)),
+        )
     }
 
     fn fetch_opcode(&mut self) {
+        if self.program_counter as usize >= PROGRAM_START as usize + self.rom_len {
+            self.ran_off_end = true;
+        }
+        self.cycles += 1;
         // 根据pc获取操作码，pc是当前程序的位置
-        let opcode = (self.memory[self.program_counter as usize] as u16) << 8
-            | self.memory[self.program_counter as usize + 1] as u16;
-        self.opcode = OpCode {
-            first: ((opcode & 0xF000) >> 12) as u8,
-            second: ((opcode & 0x0F00) >> 8) as u8,
-            third: ((opcode & 0x00F0) >> 4) as u8,
-            fourth: (opcode & 0x000F) as u8,
-        };
-        self.program_counter += 2;
+        self.opcode = self.read_opcode_at(self.program_counter);
+        self.advance_program_counter();
     }
 
-    fn process_opcode(&mut self) {
-        self.program_counter += 2; // ？
+    fn process_opcode(&mut self) -> Result<(), Chip8Error> {
+        if let Some(hook) = self.trace_hook.as_mut() {
+            let pc = self.program_counter.wrapping_sub(2) & ADDRESS_MASK;
+            hook(pc, self.opcode.merged_opcode());
+        }
 
         // 解码操作码，根据百科上的opcode表定义对应操作码的操作，https://en.wikipedia.org/wiki/CHIP-8#Opcode_table
         match (
@@ -144,12 +825,19 @@ impl Emulator {
             self.opcode.third,
             self.opcode.fourth,
         ) {
-            (0, 0, 0xE, 0) => self._00e0(),
-            (0, 0, 0xE, 0xE) => self._00ee(),
-            // 先匹配0x00E0和0x00EE，然后再匹配0x0NNN，因为NNN可能是任何符号，但是0x00E0和0x00EE是特殊操作
-            (0, _, _, _) => self._0nnn(),
-            (1, _, _, _) => self._1nnn(),
-            (2, _, _, _) => self._2nnn(),
+            // 0x00E0和0x00EE等特殊操作与0x0NNN共用开头，由system_op区分
+            (0, _, _, _) => match system_op(&self.opcode) {
+                SystemOp::Cls => self._00e0(),
+                SystemOp::Ret => self._00ee()?,
+                SystemOp::Scd => self._00cn(),
+                SystemOp::Scr => self._00fb(),
+                SystemOp::Scl => self._00fc(),
+                SystemOp::Low => self._00fe(),
+                SystemOp::High => self._00ff(),
+                SystemOp::Sys => self._0nnn(),
+            },
+            (1, _, _, _) => self._1nnn()?,
+            (2, _, _, _) => self._2nnn()?,
             (3, _, _, _) => self._3xnn(),
             (4, _, _, _) => self._4xnn(),
             (5, _, _, 0) => self._5xy0(),
@@ -166,7 +854,7 @@ impl Emulator {
             (8, _, _, 0xE) => self._8xye(),
             (9, _, _, 0) => self._9xy0(),
             (0xA, _, _, _) => self._annn(),
-            (0xB, _, _, _) => self._bnnn(),
+            (0xB, _, _, _) => self._bnnn()?,
             (0xC, _, _, _) => self._cxnn(),
             (0xD, _, _, _) => self._dxyn(),
             (0xE, _, 9, 0xE) => self._ex9e(),
@@ -177,24 +865,52 @@ impl Emulator {
             (0xF, _, 1, 8) => self._fx18(),
             (0xF, _, 1, 0xE) => self._fx1e(),
             (0xF, _, 2, 9) => self._fx29(),
+            (0xF, _, 3, 0) => self._fx30(),
             (0xF, _, 3, 3) => self._fx33(),
             (0xF, _, 5, 5) => self._fx55(),
             (0xF, _, 6, 5) => self._fx65(),
-            _ => {}
+            (0xF, _, 7, 5) => self._fx75(),
+            (0xF, _, 8, 5) => self._fx85(),
+            _ => return Err(Chip8Error::UnknownOpcode(self.opcode.merged_opcode())),
         }
+        Ok(())
     }
 
-    fn update_timer(&mut self) {
-        // 更新定时器
+    /// 更新定时器，前端需要以60Hz的频率调用
+    pub fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                println!("BEE!");
-            }
-            self.sound_timer -= 1;
+            self.set_sound_timer(self.sound_timer - 1);
+        }
+    }
+
+    /// 蜂鸣器当前是否在响（sound_timer大于0）。
+    /// 这是最简单的音频接入方式，轮询式的前端在每个60Hz的帧检查一次即可
+    pub fn is_beeping(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// 设置音频输出，默认为不发声的`NullAudio`。音频输出需要实现Send，这样模拟器可以移动到其他线程运行
+    pub fn set_audio_sink(&mut self, audio: Box<dyn AudioSink + Send>) {
+        self.audio = audio;
+    }
+
+    /// 设置蜂鸣器的回调，sound_timer变为非0时以`true`调用，回到0时以`false`调用，
+    /// 前端可以据此开关方波发生器。这会替换`set_audio_sink`设置的音频输出
+    pub fn set_sound_callback(&mut self, callback: Box<dyn FnMut(bool) + Send>) {
+        self.audio = Box::new(CallbackAudio(callback));
+    }
+
+    /// 设置sound_timer，在蜂鸣器开始和停止时通知音频输出
+    fn set_sound_timer(&mut self, value: u8) {
+        match (self.sound_timer > 0, value > 0) {
+            (false, true) => self.audio.start_beep(),
+            (true, false) => self.audio.stop_beep(),
+            _ => {}
         }
+        self.sound_timer = value;
     }
 }
 
@@ -217,7 +933,7 @@ impl Emulator {
         // self.registers[((self.opcode.merged_opcode() & 0x00F0) >> 4) as usize]
     }
 
-    ///
+    /// 获取VX的可变引用
     #[inline]
     fn get_mut_register_vx(&mut self) -> &mut u8 {
         &mut self.registers[self.opcode.second as usize]
@@ -241,10 +957,39 @@ impl Emulator {
         self.opcode.merged_opcode() & 0x0FFF
     }
 
+    /// 读取内存，地址只取低12位，超过0xFFF时回绕到0x000
+    #[inline]
+    fn read_mem(&self, address: u16) -> u8 {
+        self.memory[(address & ADDRESS_MASK) as usize]
+    }
+
+    /// 写入内存，地址只取低12位，超过0xFFF时回绕到0x000
+    #[inline]
+    fn write_mem(&mut self, address: u16, value: u8) {
+        self.memory[(address & ADDRESS_MASK) as usize] = value;
+    }
+
+    /// 将pc指向下一条指令，超过0xFFF时回绕
+    #[inline]
+    fn advance_program_counter(&mut self) {
+        self.program_counter = (self.program_counter + 2) & ADDRESS_MASK;
+    }
+
+    /// 跳转到指定地址。开启对齐检查时，奇数地址返回`Chip8Error::MisalignedJump`，pc不会改变
+    #[inline]
+    fn jump_to(&mut self, address: u16) -> Result<(), Chip8Error> {
+        let address = address & ADDRESS_MASK;
+        if self.enforce_alignment && address & 1 != 0 {
+            return Err(Chip8Error::MisalignedJump(address));
+        }
+        self.program_counter = address;
+        Ok(())
+    }
+
     /// 跳过下一个指令
     #[inline]
     fn skip_next_instruction(&mut self) {
-        self.program_counter += 2;
+        self.advance_program_counter();
     }
 
     /// 在地址NNN上调用代码例程(routine)(RCA 1802 for COSMAC VIP)，对于大多数rom来说，这个操作不是必须的。
@@ -253,34 +998,90 @@ impl Emulator {
     /// 清除屏幕
     /// disp_clear()
     fn _00e0(&mut self) {
-        self.gfx = [[0; SCREEN_WIDTH]; SCREEN_HEIGHT];
+        self.gfx = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        self.draw_flag = true;
+    }
+
+    /// 将屏幕向下滚动N行，顶部空出的行清零（SUPER-CHIP）
+    fn _00cn(&mut self) {
+        let (width, height) = self.dimensions();
+        let n = (self.get_n() as usize).min(height);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                self.gfx[y][x] = if y >= n { self.gfx[y - n][x] } else { 0 };
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    /// 将屏幕向右滚动4个像素，左侧空出的列清零（SUPER-CHIP）
+    fn _00fb(&mut self) {
+        let (width, height) = self.dimensions();
+        for row in self.gfx.iter_mut().take(height) {
+            row.copy_within(0..width - 4, 4);
+            row[..4].fill(0);
+        }
+        self.draw_flag = true;
+    }
+
+    /// 将屏幕向左滚动4个像素，右侧空出的列清零（SUPER-CHIP）
+    fn _00fc(&mut self) {
+        let (width, height) = self.dimensions();
+        for row in self.gfx.iter_mut().take(height) {
+            row.copy_within(4..width, 0);
+            row[width - 4..width].fill(0);
+        }
+        self.draw_flag = true;
+    }
+
+    /// 切换到低分辨率64x32模式并清除屏幕（SUPER-CHIP）
+    fn _00fe(&mut self) {
+        self.hires = false;
+        self._00e0();
+    }
+
+    /// 切换到高分辨率128x64模式并清除屏幕（SUPER-CHIP）
+    fn _00ff(&mut self) {
+        self.hires = true;
+        self._00e0();
     }
 
     /// 从子例程(subroutine)返回。
-    /// 当调用子例程时，我们会将当前pc存储到sp位置的stack中，并将栈指针加1，这相当于记录当前帧，
-    /// 那么当我们从子例程中返回时，我们需要将栈指针减一以指回原本pc的帧。
+    /// 当调用子例程时，我们会将当前pc压入stack中，这相当于记录当前帧，
+    /// 那么当我们从子例程中返回时，我们需要弹出栈顶以回到原本pc的帧。
+    /// 栈为空时（返回次数多于调用次数）返回`Chip8Error::StackUnderflow`，不会改变pc以外的状态。
     /// return;
-    fn _00ee(&mut self) {
-        self.stack_pointer -= 1;
-        self.program_counter = self.stack[self.stack_pointer] + 2;
+    fn _00ee(&mut self) -> Result<(), Chip8Error> {
+        let address = self.stack.pop().ok_or(Chip8Error::StackUnderflow(
+            self.program_counter.wrapping_sub(2) & ADDRESS_MASK,
+        ))?;
+        self.program_counter = address;
+        Ok(())
     }
 
     /// 跳转到地址NNN。
     /// 直接将当前程序计数器指向地址
     /// goto NNN;
-    fn _1nnn(&mut self) {
+    fn _1nnn(&mut self) -> Result<(), Chip8Error> {
         // 1nnn对应opcode的second+third+fourth地址
-        self.program_counter = self.get_nnn();
+        self.jump_to(self.get_nnn())
     }
 
     /// 在NNN处调用子例程(subroutine)
+    /// 堆栈已满时返回`Chip8Error::StackOverflow`，不会执行这次调用。
     /// *(0xNNN)()
-    fn _2nnn(&mut self) {
+    fn _2nnn(&mut self) -> Result<(), Chip8Error> {
+        if self.stack.len() >= self.stack_limit {
+            return Err(Chip8Error::StackOverflow(
+                self.program_counter.wrapping_sub(2) & ADDRESS_MASK,
+            ));
+        }
         // 因为我们需要临时跳转到地址NNN，这意味着我们应该将程序计数器的当前地址存储在堆栈中。
-        // 将程序计数器的值存入栈后，增加栈指针，防止覆盖当前栈。
-        self.stack[self.stack_pointer] = self.program_counter - 2;
-        self.stack_pointer += 1;
-        self.program_counter = self.get_nnn();
+        // fetch_opcode已经将pc指向下一条指令，所以返回时直接从这里继续执行。
+        let return_address = self.program_counter;
+        self.jump_to(self.get_nnn())?;
+        self.stack.push(return_address);
+        Ok(())
     }
 
     /// 如果VX的值等于NN，则跳过下一条指令（通常下一条指令是跳过一个代码块）
@@ -299,10 +1100,10 @@ impl Emulator {
         }
     }
 
-    /// 如果VX的值不等于VY，则跳过下一条指令（通常下一条指令是跳过一个代码块）
+    /// 如果VX的值等于VY，则跳过下一条指令（通常下一条指令是跳过一个代码块）
     /// if (Vx == Vy)
     fn _5xy0(&mut self) {
-        if self.get_register_vx() != self.get_register_vy() {
+        if self.get_register_vx() == self.get_register_vy() {
             self.skip_next_instruction();
         }
     }
@@ -313,10 +1114,10 @@ impl Emulator {
         *self.get_mut_register_vx() = self.get_nn();
     }
 
-    /// 将VX的值加上NN地址的值（进位标志不变）
+    /// 将VX的值加上NN地址的值（进位标志不变），结果超过255时按256取模回绕
     /// Vx += NN
     fn _7xnn(&mut self) {
-        *self.get_mut_register_vx() += self.get_nn();
+        *self.get_mut_register_vx() = self.get_register_vx().wrapping_add(self.get_nn());
     }
 
     /// 将VX的值设置为为VY的值
@@ -366,11 +1167,27 @@ impl Emulator {
         *self.get_mut_register_vx() = result;
     }
 
-    /// 将VX的最低有效位存储在VF中，然后将VX向右移动1
+    /// 将VX的最低有效位存储在VF中，然后将VX向右移动1。
+    /// 开启`shift_uses_vy`时先将VY复制到VX，开启`shift_by_register`时移动`VY & 0x07`位
     /// Vx >>= 1
     fn _8xy6(&mut self) {
-        self.registers[0xF] = self.get_register_vx() & 0x1;
-        *self.get_mut_register_vx() >>= 1;
+        if self.quirks.shift_by_register {
+            let vx = self.get_register_vx();
+            let amount = self.get_register_vy() & 0x07;
+            *self.get_mut_register_vx() = vx >> amount;
+            self.registers[0xF] = if amount == 0 {
+                0
+            } else {
+                (vx >> (amount - 1)) & 0x1
+            };
+            return;
+        }
+        if self.quirks.shift_uses_vy {
+            *self.get_mut_register_vx() = self.get_register_vy();
+        }
+        let vx = self.get_register_vx();
+        *self.get_mut_register_vx() = vx >> 1;
+        self.registers[0xF] = vx & 0x1;
     }
 
     /// 设置VX为VY - VX。有借位时VF设为0，没有借位时VF设为1。
@@ -383,11 +1200,27 @@ impl Emulator {
         *self.get_mut_register_vx() = result;
     }
 
-    /// 将VX的最高有效位存储在VF中，然后将VX向左移动1
+    /// 将VX的最高有效位存储在VF中，然后将VX向左移动1。
+    /// 开启`shift_uses_vy`时先将VY复制到VX，开启`shift_by_register`时移动`VY & 0x07`位
     /// Vx <<= 1
     fn _8xye(&mut self) {
-        self.registers[0xF] = self.get_register_vx() & 0x80;
-        *self.get_mut_register_vx() <<= 1;
+        if self.quirks.shift_by_register {
+            let vx = self.get_register_vx();
+            let amount = self.get_register_vy() & 0x07;
+            *self.get_mut_register_vx() = vx << amount;
+            self.registers[0xF] = if amount == 0 {
+                0
+            } else {
+                (vx >> (8 - amount)) & 0x1
+            };
+            return;
+        }
+        if self.quirks.shift_uses_vy {
+            *self.get_mut_register_vx() = self.get_register_vy();
+        }
+        let vx = self.get_register_vx();
+        *self.get_mut_register_vx() = vx << 1;
+        self.registers[0xF] = (vx & 0x80) >> 7;
     }
 
     /// 如果VX的值不等于VY，则跳过下一条指令（通常下一条指令是跳过一个代码块）
@@ -407,35 +1240,55 @@ impl Emulator {
 
     /// 跳转到V0 + 地址NNN
     /// PC = V0 + NNN
-    fn _bnnn(&mut self) {
-        self.program_counter = self.registers[0] as u16 + self.get_nnn();
+    fn _bnnn(&mut self) -> Result<(), Chip8Error> {
+        // 开启`bnnn_uses_vx`时按照BXNN解释，X是NNN的最高位
+        let register = if self.quirks.bnnn_uses_vx {
+            self.registers[self.opcode.second as usize]
+        } else {
+            self.registers[0]
+        };
+        self.jump_to(register as u16 + self.get_nnn())
     }
 
     /// 将VX设置为对一个随机数(通常为0到255)和NN进行逐位和操作的结果。
     /// Vx = rand() & NN
     fn _cxnn(&mut self) {
-        *self.get_mut_register_vx() = rand::random::<u8>() & self.get_nn();
+        *self.get_mut_register_vx() = self.rng.gen::<u8>() & self.get_nn();
     }
 
     /// 绘制一个坐标(VX, VY)的精灵，其宽度为8像素，高度为N像素。
     /// 每一行8个像素被读取为位编码，从内存位置I开始，I值在执行此指令后不会改变。
     /// 如上所述，如果精灵绘制时任何屏幕像素从设置翻转到未设置，则VF设置为1，
     /// 如果没有发生这种情况，则VF设置为0。
+    /// SUPER-CHIP高分辨率模式下N为0时绘制16x16的精灵，每行由两个字节组成，共读取32个字节。
     /// draw(Vx, Vy, N)
     fn _dxyn(&mut self) {
-        let vx = self.get_register_vx() as u16;
-        let vy = self.get_register_vy() as u16;
+        // 必须在复位VF之前读取坐标，X或Y为F时（如DFY0）绘制使用的是复位前VF的值
+        let vx = self.get_register_vx() as usize;
+        let vy = self.get_register_vy() as usize;
         self.registers[0xF] = 0; // 复位寄存器
 
-        let sprite = &self.memory
-            [self.index_register as usize..(self.index_register + self.get_n() as u16) as usize];
+        let (width, height) = self.dimensions();
+        let (rows, bytes_per_row) = if self.hires && self.get_n() == 0 {
+            (16, 2)
+        } else {
+            (self.get_n() as usize, 1)
+        };
+        let sprite_width = bytes_per_row * 8;
 
-        for j in 0..sprite.len() {
-            for i in 0..8 {
-                let y = (vy as usize + j) % SCREEN_HEIGHT;
-                let x = (vx as usize + i) % SCREEN_WIDTH;
+        for j in 0..rows {
+            let mut row = 0u16;
+            for b in 0..bytes_per_row {
+                let address = self
+                    .index_register
+                    .wrapping_add((j * bytes_per_row + b) as u16);
+                row = row << 8 | self.read_mem(address) as u16;
+            }
+            for i in 0..sprite_width {
+                let y = (vy + j) % height;
+                let x = (vx + i) % width;
 
-                if (sprite[j] & (0x80 >> i)) != 0x00 {
+                if (row & (1 << (sprite_width - 1 - i))) != 0x00 {
                     if self.gfx[y][x] == 0x01 {
                         self.registers[0xF] = 1;
                     }
@@ -443,6 +1296,7 @@ impl Emulator {
                 }
             }
         }
+        self.draw_flag = true;
     }
 
     /// 如果按下存储在VX中的键，则跳过下一条指令(通常下一条指令是跳过一个代码块的跳转)。
@@ -450,8 +1304,6 @@ impl Emulator {
     fn _ex9e(&mut self) {
         if self.keypad[self.get_register_vx() as usize] {
             self.skip_next_instruction();
-        } else {
-            self.program_counter += 2;
         }
     }
 
@@ -460,8 +1312,6 @@ impl Emulator {
     fn _exa1(&mut self) {
         if !self.keypad[self.get_register_vx() as usize] {
             self.skip_next_instruction();
-        } else {
-            self.program_counter += 2;
         }
     }
 
@@ -472,13 +1322,12 @@ impl Emulator {
     }
 
     /// 等待一个按键，然后存储到VX（阻塞操作，所有指令停止，直到下一个按键事件）。
+    /// 没有按键按下时将pc退回到当前指令，下一个周期会再次执行这条指令，从而实现阻塞。
     /// Vx = get_key()
     fn _fx0a(&mut self) {
-        self.program_counter -= 2;
-        // TODO
-        if self.keypad[self.get_register_vx() as usize] {
-            *self.get_mut_register_vx() = self.get_register_vx();
-            self.program_counter += 2;
+        match self.keypad.iter().position(|&pressed| pressed) {
+            Some(key) => *self.get_mut_register_vx() = key as u8,
+            None => self.program_counter = self.program_counter.wrapping_sub(2) & ADDRESS_MASK,
         }
     }
 
@@ -491,13 +1340,16 @@ impl Emulator {
     /// 将sound_timer的值设置为VX
     /// sound_timer(vx)
     fn _fx18(&mut self) {
-        self.sound_timer = self.get_register_vx();
+        self.set_sound_timer(self.get_register_vx());
     }
 
-    /// 添加VX到索引寄存器，VF不受影响。
+    /// 添加VX到索引寄存器，VF不受影响。结果超过0xFFF时回绕
     /// I += vx
     fn _fx1e(&mut self) {
-        self.index_register += self.get_register_vx() as u16;
+        self.index_register = self
+            .index_register
+            .wrapping_add(self.get_register_vx() as u16)
+            & ADDRESS_MASK;
     }
 
     /// 将索引寄存器设置为VX中角色的精灵位置。字符0-F(十六进制)由4x5字体表示。
@@ -506,32 +1358,1222 @@ impl Emulator {
         self.index_register = self.get_register_vx() as u16 * 5;
     }
 
-    /// 将VX的二进制编码的十六进制表示形式存储在地址i、i+1、i+2
+    /// SUPER-CHIP：将索引寄存器设置为VX中数字的8x10大字体精灵位置，每个字符占10字节。
+    /// 大字体只有数字0-9，VX大于9时只取低4位，指向大字体之后仍在保留区域内的空白数据
+    /// I = bigsprite_addr[Vx]
+    fn _fx30(&mut self) {
+        let digit = (self.get_register_vx() & 0x0F) as usize;
+        self.index_register = (BIG_FONTSET_START + digit * 10) as u16;
+    }
+
+    /// 将VX的十进制表示的百位、十位、个位分别存储在地址I、I+1、I+2，例如255存储为2、5、5，
+    /// 0存储为0、0、0。地址超过0xFFF时回绕到0x000
     /// set_BCD(Vx)
     /// *(I+0) = BCD(3);
     /// *(I+1) = BCD(2);
     /// *(I+2) = BCD(1);
     fn _fx33(&mut self) {
+        let address = self.index_register;
         let vx = self.get_register_vx();
-        self.memory[self.index_register as usize] = vx / 100;
-        self.memory[self.index_register as usize + 1] = (vx / 10) % 10;
-        // self.memory[self.index_register as usize + 2] = (vx % 100) % 10; // ?
-        self.memory[self.index_register as usize + 2] = vx % 10; // ?
+        self.write_mem(address, vx / 100);
+        self.write_mem(address.wrapping_add(1), vx / 10 % 10);
+        self.write_mem(address.wrapping_add(2), vx % 10);
     }
 
-    /// 从V0到VX(包括VX)存储在内存中，从地址I开始。每写入一个值，从I的偏移量增加1，但I本身不被修改。
+    /// 从V0到VX(包括VX)存储在内存中，从地址I开始。每写入一个值，从I的偏移量增加1，但I本身不被修改（开启`index_increment_on_store`时I会增加X+1），地址超过0xFFF时回绕。
     /// reg_dump(Vx, &I)
     fn _fx55(&mut self) {
         for i in 0..=self.opcode.second as usize {
-            self.memory[self.index_register as usize + i] = self.registers[i];
+            self.write_mem(
+                self.index_register.wrapping_add(i as u16),
+                self.registers[i],
+            );
+        }
+        if self.quirks.index_increment_on_store {
+            self.index_register = self
+                .index_register
+                .wrapping_add(self.opcode.second as u16 + 1)
+                & ADDRESS_MASK;
         }
     }
 
-    /// 从V0到VX(包括VX)用内存中的值填充，从地址I开始。每读取一个值，从I的偏移量增加1，但I本身不被修改。
+    /// 从V0到VX(包括VX)用内存中的值填充，从地址I开始。每读取一个值，从I的偏移量增加1，但I本身不被修改（开启`index_increment_on_store`时I会增加X+1），地址超过0xFFF时回绕。
     /// reg_load(Vx, &I)
     fn _fx65(&mut self) {
         for i in 0..=self.opcode.second as usize {
-            self.registers[i] = self.memory[self.index_register as usize + i]
+            self.registers[i] = self.read_mem(self.index_register.wrapping_add(i as u16));
+        }
+        if self.quirks.index_increment_on_store {
+            self.index_register = self
+                .index_register
+                .wrapping_add(self.opcode.second as u16 + 1)
+                & ADDRESS_MASK;
+        }
+    }
+
+    /// SUPER-CHIP：将V0到VX(包括VX)保存到RPL用户标志中，X最大为7
+    fn _fx75(&mut self) {
+        let count = (self.opcode.second as usize).min(RPL_SIZE - 1) + 1;
+        self.rpl[..count].copy_from_slice(&self.registers[..count]);
+    }
+
+    /// SUPER-CHIP：从RPL用户标志中读取V0到VX(包括VX)，X最大为7
+    fn _fx85(&mut self) {
+        let count = (self.opcode.second as usize).min(RPL_SIZE - 1) + 1;
+        self.registers[..count].copy_from_slice(&self.rpl[..count]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 创建一个固定种子的模拟器并加载rom
+    fn emulator_with_rom(rom: &[u8]) -> Emulator {
+        let mut emulator = Emulator::with_seed(0);
+        emulator.load_rom_bytes(rom).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn sequential_loads_each_execute_once() {
+        let mut emulator = emulator_with_rom(&[0x60, 0x11, 0x61, 0x22, 0x62, 0x33]);
+        for _ in 0..3 {
+            emulator.step_cpu().unwrap();
+        }
+        assert_eq!(emulator.registers()[..3], [0x11, 0x22, 0x33]);
+        assert_eq!(emulator.program_counter(), 0x206);
+    }
+
+    #[test]
+    fn decode_splits_nibbles_in_order() {
+        let opcode = decode(0xD0, 0x1F);
+        assert_eq!(
+            (opcode.first, opcode.second, opcode.third, opcode.fourth),
+            (0xD, 0x0, 0x1, 0xF)
+        );
+        assert_eq!(opcode.merged_opcode(), 0xD01F);
+    }
+
+    #[test]
+    fn _5xy0_skips_when_equal() {
+        // V0 = V1 = 0x07
+        let mut emulator = emulator_with_rom(&[0x60, 0x07, 0x61, 0x07, 0x50, 0x10]);
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(emulator.program_counter(), 0x208);
+    }
+
+    #[test]
+    fn _5xy0_does_not_skip_when_different() {
+        let mut emulator = emulator_with_rom(&[0x60, 0x07, 0x61, 0x08, 0x50, 0x10]);
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(emulator.program_counter(), 0x206);
+    }
+
+    #[test]
+    fn _7xnn_wraps_without_touching_vf() {
+        // VF = 0x55；V0 = 0xFF；V0 += 0xFF
+        let mut emulator = emulator_with_rom(&[0x6F, 0x55, 0x60, 0xFF, 0x70, 0xFF]);
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(emulator.registers()[0], 0xFE);
+        assert_eq!(emulator.registers()[0xF], 0x55);
+    }
+
+    #[test]
+    fn _7xnn_overflow_keeps_low_byte() {
+        // V3 = 0xF0；V3 += 0x30
+        let mut emulator = emulator_with_rom(&[0x63, 0xF0, 0x73, 0x30]);
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.registers()[3], 0x20);
+        assert_eq!(emulator.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn program_counter_wraps_to_low_memory() {
+        let mut emulator = emulator_with_rom(&[]);
+        emulator.memory[0xFFE..].copy_from_slice(&[0x60, 0x2A]);
+        emulator.program_counter = 0xFFE;
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.registers()[0], 0x2A);
+        assert_eq!(emulator.program_counter(), 0x000);
+
+        // 跳过指令时同样回绕
+        emulator.memory[0xFFE..].copy_from_slice(&[0x30, 0x2A]);
+        emulator.program_counter = 0xFFE;
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.program_counter(), 0x002);
+    }
+
+    #[test]
+    fn _8xye_sets_vf_to_shifted_out_bit() {
+        // V0 = 0x81；V0 <<= 1
+        let mut emulator = emulator_with_rom(&[0x60, 0x81, 0x80, 0x0E]);
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.registers()[0], 0x02);
+        assert_eq!(emulator.registers()[0xF], 1);
+
+        // V0 = 0x41；V0 <<= 1
+        let mut emulator = emulator_with_rom(&[0x60, 0x41, 0x80, 0x0E]);
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.registers()[0], 0x82);
+        assert_eq!(emulator.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn fx0a_waits_for_key_press() {
+        let mut emulator = emulator_with_rom(&[0xF3, 0x0A]);
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(emulator.program_counter(), 0x200);
+
+        emulator.keypad[0xA] = true;
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.registers()[3], 0xA);
+        assert_eq!(emulator.program_counter(), 0x202);
+    }
+
+    #[test]
+    fn load_rom_bytes_rejects_oversized_rom() {
+        let mut emulator = Emulator::with_seed(0);
+        let error = emulator.load_rom_bytes(&[0; 4000]).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("4000"), "{}", message);
+        assert!(message.contains("3584"), "{}", message);
+        assert!(emulator.load_rom_bytes(&[0; 3584]).is_ok());
+    }
+
+    #[test]
+    fn sprite_wraps_at_bottom_right_corner() {
+        // V0 = 62；V1 = 30；I = 0x20A；绘制3行0xF0
+        let mut emulator = emulator_with_rom(&[
+            0x60, 62, 0x61, 30, 0xA2, 0x0A, 0xD0, 0x13, 0x12, 0x08, 0xF0, 0xF0, 0xF0,
+        ]);
+        emulator.run_cycles(4).unwrap();
+        for y in [30, 31, 0] {
+            for x in [62, 63, 0, 1] {
+                assert!(emulator.pixel(x, y), "({}, {})", x, y);
+            }
+            assert!(!emulator.pixel(2, y));
+            assert!(!emulator.pixel(61, y));
+        }
+        assert!(!emulator.pixel(0, 1));
+        assert!(!emulator.pixel(0, 29));
+    }
+
+    #[test]
+    fn keypad_string_marks_pressed_keys() {
+        let mut emulator = Emulator::with_seed(0);
+        emulator.keypad[0x1] = true;
+        emulator.keypad[0xF] = true;
+        assert_eq!(
+            emulator.keypad_string(),
+            "[1] 2  3  C \n 4  5  6  D \n 7  8  9  E \n A  0  B [F]"
+        );
+    }
+
+    #[test]
+    fn load_rom_rejects_oversized_file() {
+        let path = std::env::temp_dir().join(format!("chip8-oversized-{}.ch8", std::process::id()));
+        std::fs::write(&path, [0u8; 4000]).unwrap();
+        let mut emulator = Emulator::with_seed(0);
+        let result = emulator.load_rom(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().to_string().contains("ROM过大"));
+    }
+
+    #[test]
+    fn sprite_at_right_edge_wraps_to_column_zero() {
+        // V0 = 63；V1 = 0；I = 0x20A；绘制1行0xE0
+        let mut emulator =
+            emulator_with_rom(&[0x60, 63, 0x61, 0, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0xE0]);
+        emulator.run_cycles(4).unwrap();
+        assert!(emulator.pixel(63, 0));
+        assert!(emulator.pixel(0, 0));
+        assert!(emulator.pixel(1, 0));
+        assert!(!emulator.pixel(2, 0));
+        assert!(!emulator.pixel(62, 0));
+    }
+
+    #[test]
+    fn load_rom_bytes_copies_to_program_start() {
+        let emulator = emulator_with_rom(&[0x60, 0x2A, 0x12, 0x00]);
+        assert_eq!(emulator.memory[0x200..0x204], [0x60, 0x2A, 0x12, 0x00]);
+        assert_eq!(emulator.program(), [0x60, 0x2A, 0x12, 0x00]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn emulator_cycle_matches_cycle() {
+        // V0 = 5；DT = V0；V0 += 1
+        let rom = [0x60, 0x05, 0xF0, 0x15, 0x70, 0x01];
+        let mut current = emulator_with_rom(&rom);
+        let mut deprecated = emulator_with_rom(&rom);
+        for _ in 0..3 {
+            current.cycle().unwrap();
+            deprecated.emulator_cycle();
+            assert_eq!(current.state(), deprecated.state());
+        }
+    }
+
+    #[test]
+    fn ran_off_end_is_set_after_passing_the_rom() {
+        let mut emulator = emulator_with_rom(&[0x60, 0x01, 0x61, 0x02]);
+        emulator.run_cycles(2).unwrap();
+        assert!(!emulator.ran_off_end());
+        emulator.step_cpu().unwrap();
+        assert!(emulator.ran_off_end());
+    }
+
+    #[test]
+    fn framebuffer_and_pixel_agree() {
+        // V0 = 3；V1 = 2；I = 0x20A；绘制1行0x80
+        let mut emulator =
+            emulator_with_rom(&[0x60, 3, 0x61, 2, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0x80]);
+        emulator.run_cycles(4).unwrap();
+        assert_eq!(emulator.framebuffer().nth(2).unwrap()[3], 1);
+        assert!(emulator.pixel(3, 2));
+        let lit = emulator.framebuffer().flatten().filter(|&&p| p != 0);
+        assert_eq!(lit.count(), 1);
+    }
+
+    #[test]
+    fn pixel_outside_screen_is_unlit() {
+        let mut emulator = Emulator::with_seed(0);
+        emulator.gfx[0][DISPLAY_WIDTH] = 1;
+        assert!(!emulator.pixel(DISPLAY_WIDTH, 0));
+        assert!(!emulator.pixel(0, DISPLAY_HEIGHT));
+        assert!(!emulator.pixel(usize::MAX, usize::MAX));
+    }
+
+    #[test]
+    fn timers_only_tick_in_tick_timers() {
+        // V0 = 5；DT = V0；ST = V0；然后原地循环
+        let mut emulator = emulator_with_rom(&[0x60, 0x05, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06]);
+        emulator.run_cycles(10).unwrap();
+        assert_eq!(emulator.timers(), (5, 5));
+        emulator.tick_timers();
+        assert_eq!(emulator.timers(), (4, 4));
+        emulator.cycle().unwrap();
+        assert_eq!(emulator.timers(), (3, 3));
+    }
+
+    #[test]
+    fn draw_flag_is_set_by_draw_only() {
+        // 跳转到0x202；在(0, 0)绘制0行的精灵
+        let mut emulator = emulator_with_rom(&[0x12, 0x02, 0xD0, 0x00]);
+        emulator.take_draw_flag();
+        emulator.cycle().unwrap();
+        assert!(!emulator.take_draw_flag());
+        emulator.cycle().unwrap();
+        assert!(emulator.take_draw_flag());
+        assert!(!emulator.take_draw_flag());
+    }
+
+    #[test]
+    fn ten_cpu_steps_per_timer_tick() {
+        // V0 = 0x20；DT = V0；然后原地循环
+        let mut emulator = emulator_with_rom(&[0x60, 0x20, 0xF0, 0x15, 0x12, 0x04]);
+        emulator.run_cycles(2).unwrap();
+        for _ in 0..10 {
+            emulator.step_cpu().unwrap();
+        }
+        emulator.tick_timers();
+        assert_eq!(emulator.timers().0, 0x1F);
+    }
+
+    #[test]
+    fn same_seed_gives_same_random_numbers() {
+        // V0 = rand()；V1 = rand()；V2 = rand() & 0x0F
+        let rom = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0x0F];
+        let mut first = Emulator::with_seed(42);
+        let mut second = Emulator::with_seed(42);
+        for emulator in [&mut first, &mut second] {
+            emulator.load_rom_bytes(&rom).unwrap();
+            emulator.run_cycles(3).unwrap();
         }
+        assert_eq!(first.registers(), second.registers());
+        assert!(first.registers()[2] <= 0x0F);
+    }
+
+    #[test]
+    fn reset_keeps_loaded_rom() {
+        let rom = [0x60, 0x2A, 0xA3, 0x00, 0x22, 0x08, 0x00, 0x00, 0x12, 0x08];
+        let mut emulator = emulator_with_rom(&rom);
+        emulator.run_cycles(3).unwrap();
+        emulator.reset();
+        assert_eq!(emulator.state(), Emulator::with_seed(0).state());
+        assert_eq!(emulator.program_counter(), 0x200);
+        assert_eq!(emulator.program(), rom);
+        assert_eq!(emulator.memory[..FONTSET.len()], FONTSET);
+    }
+
+    #[test]
+    fn would_collide_does_not_draw() {
+        let mut emulator = Emulator::with_seed(0);
+        emulator.gfx[5][10] = 1;
+        assert!(emulator.would_collide(8, 4, &[0x00, 0x20]));
+        assert!(!emulator.would_collide(20, 20, &[0xFF, 0xFF]));
+        assert!(!emulator.would_collide(8, 4, &[0x20, 0x00]));
+        let lit = emulator.framebuffer().flatten().filter(|&&p| p != 0);
+        assert_eq!(lit.count(), 1);
+    }
+
+    #[test]
+    fn _7xnn_into_vf_has_no_carry() {
+        // VF = 0xF0；VF += 0x10
+        let mut emulator = emulator_with_rom(&[0x6F, 0xF0, 0x7F, 0x10]);
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.registers()[0xF], 0x00);
+    }
+
+    #[test]
+    fn rng_state_restores_random_sequence() {
+        // V0 = rand()；V1 = rand()
+        let mut emulator = emulator_with_rom(&[0xC0, 0xFF, 0xC1, 0xFF]);
+        let state = emulator.rng_state();
+        emulator.run_cycles(2).unwrap();
+        let drawn = emulator.registers()[..2].to_vec();
+
+        emulator.reset();
+        emulator.set_rng_state(&state).unwrap();
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.registers()[..2], drawn);
+        assert!(emulator.set_rng_state(&state[1..]).is_err());
+    }
+
+    #[test]
+    fn lint_rom_reports_unsupported_and_out_of_range() {
+        // 0x200: 00FD；0x202: 跳转到0x800；0x204: I = 0xFFE；0x206: F355
+        let emulator = emulator_with_rom(&[0x00, 0xFD, 0x18, 0x00, 0xAF, 0xFE, 0xF3, 0x55]);
+        let lints = emulator.lint_rom();
+        let addresses: Vec<u16> = lints.iter().map(|lint| lint.address).collect();
+        assert_eq!(addresses, [0x200, 0x202, 0x206]);
+        assert!(lints[0].message.contains("SUPER-CHIP"));
+    }
+
+    /// 使用指定quirks运行rom的前n条指令
+    fn run_with_quirks(quirks: Quirks, rom: &[u8], n: usize) -> Emulator {
+        let mut emulator = Emulator::with_seed(0);
+        emulator.set_quirks(quirks);
+        emulator.load_rom_bytes(rom).unwrap();
+        emulator.run_cycles(n).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn shift_uses_vy_quirk() {
+        // V0 = 0x01；V1 = 0x04；V0 >>= 1
+        let rom = [0x60, 0x01, 0x61, 0x04, 0x80, 0x16];
+        let modern = run_with_quirks(Quirks::default(), &rom, 3);
+        assert_eq!((modern.registers()[0], modern.registers()[0xF]), (0x00, 1));
+
+        let quirks = Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        };
+        let vip = run_with_quirks(quirks, &rom, 3);
+        assert_eq!((vip.registers()[0], vip.registers()[0xF]), (0x02, 0));
+    }
+
+    #[test]
+    fn index_increment_on_store_quirk() {
+        // I = 0x300；F255；F165
+        let rom = [0xA3, 0x00, 0xF2, 0x55, 0xF1, 0x65];
+        assert_eq!(
+            run_with_quirks(Quirks::default(), &rom, 3).index_register(),
+            0x300
+        );
+
+        let quirks = Quirks {
+            index_increment_on_store: true,
+            ..Quirks::default()
+        };
+        assert_eq!(run_with_quirks(quirks, &rom, 3).index_register(), 0x305);
+    }
+
+    #[test]
+    fn bnnn_uses_vx_quirk() {
+        // V0 = 0x10；V3 = 0x20；B300
+        let rom = [0x60, 0x10, 0x63, 0x20, 0xB3, 0x00];
+        assert_eq!(
+            run_with_quirks(Quirks::default(), &rom, 3).program_counter(),
+            0x310
+        );
+
+        let quirks = Quirks {
+            bnnn_uses_vx: true,
+            ..Quirks::default()
+        };
+        assert_eq!(run_with_quirks(quirks, &rom, 3).program_counter(), 0x320);
+    }
+
+    #[test]
+    fn reseed_on_load_repeats_random_numbers() {
+        // V0 = rand()；V1 = rand()
+        let rom = [0xC0, 0xFF, 0xC1, 0xFF];
+        let run = || {
+            let mut emulator = Emulator::new();
+            emulator.set_reseed_on_load(Some(7));
+            emulator.load_rom_bytes(&rom).unwrap();
+            emulator.run_cycles(2).unwrap();
+            emulator.registers()[..2].to_vec()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn dxyn_reads_vf_coordinate_before_reset() {
+        // VF = 10；V1 = 3；I = 0x20A；DF11：x取VF
+        let mut emulator =
+            emulator_with_rom(&[0x6F, 10, 0x61, 3, 0xA2, 0x0A, 0xDF, 0x11, 0x12, 0x08, 0x80]);
+        emulator.run_cycles(4).unwrap();
+        assert!(emulator.pixel(10, 3));
+        assert!(!emulator.pixel(0, 3));
+        assert_eq!(emulator.registers()[0xF], 0);
+
+        // VF = 5；V1 = 2；D1F1：y取VF
+        let mut emulator =
+            emulator_with_rom(&[0x6F, 5, 0x61, 2, 0xA2, 0x0A, 0xD1, 0xF1, 0x12, 0x08, 0x80]);
+        emulator.run_cycles(4).unwrap();
+        assert!(emulator.pixel(2, 5));
+        assert!(!emulator.pixel(2, 0));
+    }
+
+    #[test]
+    fn accessors_report_cpu_state() {
+        // V2 = 0x33；I = 0x345；DT = V2；ST = V2；调用0x20C
+        let mut emulator = emulator_with_rom(&[
+            0x62, 0x33, 0xA3, 0x45, 0xF2, 0x15, 0xF2, 0x18, 0x22, 0x0C, 0x00, 0x00, 0x12, 0x0C,
+        ]);
+        emulator.run_cycles(5).unwrap();
+        assert_eq!(emulator.registers()[2], 0x33);
+        assert_eq!(emulator.index_register(), 0x345);
+        assert_eq!(emulator.program_counter(), 0x20C);
+        assert_eq!(emulator.stack_pointer(), 1);
+        assert_eq!(emulator.timers(), (0x33, 0x33));
+    }
+
+    /// 检查COSMAC VIP行为的小rom：通过时VE = 1，失败时VE = 2
+    const STRICT_CHECK_ROM: [u8; 32] = [
+        0x60, 0xFF, // 0x200: V0 = 0xFF
+        0x61, 0x02, // 0x202: V1 = 0x02
+        0x80, 0x16, // 0x204: V0 = V1 >> 1（现代解释器为V0 >> 1）
+        0x30, 0x01, // 0x206: if V0 == 0x01 跳过下一条
+        0x12, 0x1C, // 0x208: 失败
+        0x60, 0x05, // 0x20A: V0 = 0x05
+        0xA3, 0x00, // 0x20C: I = 0x300
+        0xF0, 0x55, // 0x20E: [I] = V0，之后I = 0x301（现代解释器I不变）
+        0x60, 0x00, // 0x210: V0 = 0x00
+        0xF0, 0x65, // 0x212: V0 = [I]
+        0x30, 0x00, // 0x214: if V0 == 0x00 跳过下一条
+        0x12, 0x1C, // 0x216: 失败
+        0x6E, 0x01, // 0x218: 通过，VE = 1
+        0x12, 0x1A, // 0x21A: 停在这里
+        0x6E, 0x02, // 0x21C: 失败，VE = 2
+        0x12, 0x1E, // 0x21E: 停在这里
+    ];
+
+    fn run_strict_check(mut emulator: Emulator) -> u8 {
+        emulator.load_rom_bytes(&STRICT_CHECK_ROM).unwrap();
+        assert_eq!(
+            emulator.run_until(|emulator| emulator.registers()[0xE] != 0, 100),
+            Ok(true)
+        );
+        emulator.registers()[0xE]
+    }
+
+    #[test]
+    fn strict_passes_check_rom_that_default_fails() {
+        assert_eq!(run_strict_check(Emulator::strict()), 1);
+        assert_eq!(run_strict_check(Emulator::with_seed(0)), 2);
+
+        // 固定种子的模拟器切换到相同的quirks后结果与strict一致
+        let mut seeded = Emulator::with_seed(0);
+        seeded.set_quirks(Quirks::cosmac_vip());
+        assert_eq!(seeded.quirks(), Emulator::strict().quirks());
+        assert_eq!(run_strict_check(seeded), 1);
+    }
+
+    #[test]
+    fn current_opcode_category_looks_at_pc() {
+        let mut emulator = emulator_with_rom(&[0x60, 0x01, 0xD0, 0x15]);
+        assert_eq!(
+            emulator.current_opcode_category(),
+            OpcodeCategory::Arithmetic
+        );
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.current_opcode_category(), OpcodeCategory::Display);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_state_round_trip() {
+        // 循环：V0 += 1，V1 = 随机数，M[I] = BCD(V0)
+        let mut emulator =
+            emulator_with_rom(&[0xA3, 0x00, 0x70, 0x01, 0xC1, 0xFF, 0xF0, 0x33, 0x12, 0x02]);
+        emulator.run_frame(7).unwrap();
+        let snapshot = emulator.save_state();
+        let expected = (emulator.state(), emulator.memory, emulator.rng_state());
+
+        emulator.run_frame(20).unwrap();
+        assert_ne!(emulator.state(), expected.0);
+
+        emulator.load_state(&snapshot).unwrap();
+        assert_eq!(
+            (emulator.state(), emulator.memory, emulator.rng_state()),
+            expected
+        );
+    }
+
+    #[test]
+    fn step_halts_at_breakpoint() {
+        let mut emulator = emulator_with_rom(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x06]);
+        emulator.add_breakpoint(0x204);
+        let mut steps = 0;
+        while let StepResult::Executed(_) = emulator.step().unwrap() {
+            steps += 1;
+            assert!(steps < 100);
+        }
+        assert_eq!(emulator.program_counter(), 0x204);
+        assert_eq!(emulator.registers()[2], 0);
+
+        // 再次step会执行断点处的指令
+        assert_eq!(
+            emulator.step(),
+            Ok(StepResult::Executed(decode(0x62, 0x03)))
+        );
+        emulator.remove_breakpoint(0x204);
+        assert_eq!(emulator.registers()[2], 3);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn fx0a_wait_resumes_after_load_state() {
+        // V0 = 1；等待按键存入V5
+        let mut emulator = emulator_with_rom(&[0x60, 0x01, 0xF5, 0x0A]);
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(emulator.program_counter(), 0x202);
+        let snapshot = emulator.save_state();
+
+        let mut restored = Emulator::with_seed(1);
+        restored.load_state(&snapshot).unwrap();
+        restored.step_cpu().unwrap();
+        assert_eq!(restored.program_counter(), 0x202);
+        restored.keypad[0x7] = true;
+        restored.step_cpu().unwrap();
+        assert_eq!(restored.registers()[5], 0x7);
+        assert_eq!(restored.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn fresh_state_starts_at_program_start() {
+        let state = Emulator::new().state();
+        assert_eq!(state.program_counter, 0x200);
+        assert_eq!(state.stack_pointer, 0);
+        assert_eq!(state.registers, [0; 16]);
+    }
+
+    #[test]
+    fn shift_left_uses_vy_quirk() {
+        // V0 = 0x01；V1 = 0x81；V0 <<= 1
+        let rom = [0x60, 0x01, 0x61, 0x81, 0x80, 0x1E];
+        let modern = run_with_quirks(Quirks::default(), &rom, 3);
+        assert_eq!((modern.registers()[0], modern.registers()[0xF]), (0x02, 0));
+
+        let vip = run_with_quirks(Quirks::cosmac_vip(), &rom, 3);
+        assert_eq!((vip.registers()[0], vip.registers()[0xF]), (0x02, 1));
+        assert_eq!(vip.registers()[1], 0x81);
+    }
+
+    #[test]
+    fn fx65_index_increment_quirk() {
+        // I = 0x300；F365
+        let rom = [0xA3, 0x00, 0xF3, 0x65];
+        assert_eq!(
+            run_with_quirks(Quirks::default(), &rom, 2).index_register(),
+            0x300
+        );
+        assert_eq!(
+            run_with_quirks(Quirks::cosmac_vip(), &rom, 2).index_register(),
+            0x304
+        );
+    }
+
+    #[test]
+    fn audio_sink_is_notified_on_start_and_stop() {
+        use std::sync::{Arc, Mutex};
+
+        struct MockAudio(Arc<Mutex<Vec<bool>>>);
+
+        impl AudioSink for MockAudio {
+            fn start_beep(&mut self) {
+                self.0.lock().unwrap().push(true);
+            }
+
+            fn stop_beep(&mut self) {
+                self.0.lock().unwrap().push(false);
+            }
+        }
+
+        // V0 = 2；ST = V0
+        let mut emulator = emulator_with_rom(&[0x60, 0x02, 0xF0, 0x18]);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        emulator.set_audio_sink(Box::new(MockAudio(events.clone())));
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(*events.lock().unwrap(), [true]);
+        emulator.tick_timers();
+        emulator.tick_timers();
+        emulator.tick_timers();
+        assert_eq!(*events.lock().unwrap(), [true, false]);
+    }
+
+    #[test]
+    fn bnnn_same_bytes_different_targets() {
+        // V0 = 0x02；V1 = 0x08；B124
+        let rom = [0x60, 0x02, 0x61, 0x08, 0xB1, 0x24];
+        let quirks = Quirks {
+            bnnn_uses_vx: true,
+            ..Quirks::default()
+        };
+        let b0nn = run_with_quirks(Quirks::default(), &rom, 3);
+        let bxnn = run_with_quirks(quirks, &rom, 3);
+        assert_eq!(b0nn.program_counter(), 0x126);
+        assert_eq!(bxnn.program_counter(), 0x12C);
+    }
+
+    #[test]
+    fn release_all_keys_clears_keypad() {
+        let mut emulator = Emulator::with_seed(0);
+        emulator.keypad[0x1] = true;
+        emulator.keypad[0x5] = true;
+        emulator.keypad[0xF] = true;
+        emulator.release_all_keys();
+        assert_eq!(emulator.keypad, [false; 16]);
+        assert!(!emulator.keypad_string().contains('['));
+    }
+
+    #[test]
+    fn sound_callback_fires_on_transitions() {
+        use std::sync::{Arc, Mutex};
+
+        // V0 = 1；ST = V0；ST = V0
+        let mut emulator = emulator_with_rom(&[0x60, 0x01, 0xF0, 0x18, 0xF0, 0x18]);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        emulator.set_sound_callback(Box::new(move |on| sink.lock().unwrap().push(on)));
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(*events.lock().unwrap(), [true]);
+        emulator.tick_timers();
+        assert_eq!(*events.lock().unwrap(), [true, false]);
+        emulator.tick_timers();
+        emulator.step_cpu().unwrap();
+        assert_eq!(*events.lock().unwrap(), [true, false, true]);
+    }
+
+    #[test]
+    fn hires_draws_16x16_sprite() {
+        // 00FF；I = 0x20A；D000；00FE；原地循环；0x20A开始是32字节的精灵
+        let mut rom = vec![0x00, 0xFF, 0xA2, 0x0A, 0xD0, 0x00, 0x00, 0xFE, 0x12, 0x08];
+        rom.extend([0xFF; 32]);
+        let mut emulator = emulator_with_rom(&rom);
+        emulator.run_cycles(3).unwrap();
+        assert!(emulator.hires());
+        assert!((0..16).all(|y| (0..16).all(|x| emulator.pixel(x, y))));
+        assert!(!emulator.pixel(16, 0));
+        assert!(!emulator.pixel(0, 16));
+        assert_eq!(emulator.framebuffer().len(), HIRES_HEIGHT);
+        assert!(emulator.framebuffer().all(|row| row.len() == HIRES_WIDTH));
+
+        emulator.step_cpu().unwrap();
+        assert!(!emulator.hires());
+        assert!(!emulator.pixel(0, 0));
+        assert_eq!(emulator.framebuffer().len(), DISPLAY_HEIGHT);
+        assert!(emulator.framebuffer().all(|row| row.len() == DISPLAY_WIDTH));
+    }
+
+    #[test]
+    fn _00cn_scrolls_down() {
+        let mut emulator = emulator_with_rom(&[0x00, 0xC4]);
+        emulator.gfx[0][..DISPLAY_WIDTH].fill(1);
+        emulator.gfx[DISPLAY_HEIGHT - 1][0] = 1;
+        emulator.step_cpu().unwrap();
+        assert!((0..4).all(|y| !emulator.pixel(0, y)));
+        assert!((0..DISPLAY_WIDTH).all(|x| emulator.pixel(x, 4)));
+        // 滚出屏幕底部的像素丢失
+        assert!(!emulator.pixel(0, 3));
+        let lit = emulator.framebuffer().flatten().filter(|&&p| p != 0);
+        assert_eq!(lit.count(), DISPLAY_WIDTH);
+    }
+
+    #[test]
+    fn is_beeping_follows_sound_timer() {
+        // V0 = 2；ST = V0
+        let mut emulator = emulator_with_rom(&[0x60, 0x02, 0xF0, 0x18]);
+        emulator.step_cpu().unwrap();
+        assert!(!emulator.is_beeping());
+        emulator.step_cpu().unwrap();
+        assert!(emulator.is_beeping());
+        emulator.tick_timers();
+        assert!(emulator.is_beeping());
+        emulator.tick_timers();
+        assert!(!emulator.is_beeping());
+    }
+
+    #[test]
+    fn _00cn_scrolls_pattern_in_hires() {
+        // 00FF；00C2
+        let mut emulator = emulator_with_rom(&[0x00, 0xFF, 0x00, 0xC2]);
+        emulator.step_cpu().unwrap();
+        emulator.gfx[0][5] = 1;
+        emulator.gfx[1][100] = 1;
+        emulator.gfx[HIRES_HEIGHT - 1][7] = 1;
+        emulator.step_cpu().unwrap();
+        assert!(emulator.pixel(5, 2));
+        assert!(emulator.pixel(100, 3));
+        assert!((0..2).all(|y| (0..HIRES_WIDTH).all(|x| !emulator.pixel(x, y))));
+        assert!(!emulator.pixel(7, HIRES_HEIGHT - 1));
+        assert!(!emulator.pixel(7, 1));
+    }
+
+    #[test]
+    fn deep_recursion_stops_without_panicking() {
+        // 0x200：调用0x200自身
+        let mut emulator = emulator_with_rom(&[0x22, 0x00]);
+        emulator.run_cycles(16).unwrap();
+        assert_eq!(emulator.stack_pointer(), 16);
+        assert!(emulator.step_cpu().is_err());
+        assert_eq!(emulator.stack_pointer(), 16);
+    }
+
+    #[test]
+    fn return_with_empty_stack_is_handled() {
+        let mut emulator = emulator_with_rom(&[0x00, 0xEE]);
+        assert!(emulator.step_cpu().is_err());
+        assert_eq!(emulator.stack_pointer(), 0);
+    }
+
+    #[test]
+    fn _00fb_and_00fc_scroll_by_four_columns() {
+        // 00FB；00FC；00FC
+        let mut emulator = emulator_with_rom(&[0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC]);
+        for y in 0..DISPLAY_HEIGHT {
+            emulator.gfx[y][1] = 1;
+            emulator.gfx[y][DISPLAY_WIDTH - 2] = 1;
+        }
+        emulator.step_cpu().unwrap();
+        assert!((0..DISPLAY_HEIGHT).all(|y| emulator.pixel(5, y)));
+        // 滚出右边缘的像素丢失
+        assert!(!emulator.pixel(DISPLAY_WIDTH - 2, 0));
+        assert!(!emulator.pixel(1, 0));
+
+        emulator.step_cpu().unwrap();
+        assert!((0..DISPLAY_HEIGHT).all(|y| emulator.pixel(1, y)));
+        emulator.step_cpu().unwrap();
+        let lit = emulator.framebuffer().flatten().filter(|&&p| p != 0);
+        assert_eq!(lit.count(), 0);
+    }
+
+    #[test]
+    fn trace_hook_sees_each_instruction() {
+        use std::sync::{Arc, Mutex};
+
+        let mut emulator = emulator_with_rom(&[0x60, 0x01, 0x12, 0x00]);
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let sink = trace.clone();
+        emulator.set_trace_hook(Some(Box::new(move |pc, opcode| {
+            sink.lock().unwrap().push((pc, opcode))
+        })));
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(*trace.lock().unwrap(), [(0x200, 0x6001), (0x202, 0x1200)]);
+
+        emulator.set_trace_hook(None);
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(trace.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn resolution_switch_reports_dimensions_and_clears() {
+        // 00FF；00FE
+        let mut emulator = emulator_with_rom(&[0x00, 0xFF, 0x00, 0xFE]);
+        assert_eq!(emulator.dimensions(), (64, 32));
+        emulator.gfx[0][0] = 1;
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.dimensions(), (128, 64));
+        assert!(!emulator.pixel(0, 0));
+        emulator.gfx[0][0] = 1;
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.dimensions(), (64, 32));
+        assert!(!emulator.pixel(0, 0));
+    }
+
+    #[test]
+    fn scaled_dimensions_default_to_window_size() {
+        let mut emulator = Emulator::with_seed(0);
+        assert_eq!(emulator.scaled_dimensions(), (640, 320));
+        assert_eq!(emulator.scaled_dimensions(), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        emulator.set_display_scale(4);
+        assert_eq!(emulator.scaled_dimensions(), (256, 128));
+    }
+
+    #[test]
+    fn run_until_pc_leaves_stops_after_loop_exits() {
+        // 0x200: V0 += 1；0x202: if V0 == 5 跳过；0x204: 跳转到0x200；0x206: 原地循环
+        let rom = [0x70, 0x01, 0x30, 0x05, 0x12, 0x00, 0x12, 0x06];
+        let mut emulator = emulator_with_rom(&rom);
+        assert_eq!(emulator.run_until_pc_leaves(0x200..0x206, 3), Ok(false));
+
+        let mut emulator = emulator_with_rom(&rom);
+        assert_eq!(emulator.run_until_pc_leaves(0x200..0x206, 100), Ok(true));
+        assert_eq!(emulator.program_counter(), 0x206);
+        assert_eq!(emulator.registers()[0], 5);
+
+        // 无法识别的操作码与step_cpu一样以Chip8Error返回
+        let mut emulator = emulator_with_rom(&[0x50, 0x11]);
+        assert_eq!(
+            emulator.run_until_pc_leaves(0x200..0x206, 100),
+            Err(Chip8Error::UnknownOpcode(0x5011))
+        );
+    }
+
+    #[test]
+    fn dxy0_collides_on_overlap() {
+        // 00FF；I = 0x20C；D000；D000；原地循环；0x20C开始是32字节的精灵
+        let mut rom = vec![
+            0x00, 0xFF, 0xA2, 0x0C, 0xD0, 0x00, 0xD0, 0x00, 0x12, 0x08, 0, 0,
+        ];
+        rom.extend([0xFF; 32]);
+        let mut emulator = emulator_with_rom(&rom);
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(emulator.registers()[0xF], 0);
+        let lit = emulator.framebuffer().flatten().filter(|&&p| p != 0);
+        assert_eq!(lit.count(), 256);
+
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.registers()[0xF], 1);
+        let lit = emulator.framebuffer().flatten().filter(|&&p| p != 0);
+        assert_eq!(lit.count(), 0);
+    }
+
+    #[test]
+    fn call_and_return_resumes_after_call() {
+        let mut rom = vec![0x23, 0x00];
+        rom.resize(0x100, 0);
+        rom.extend([0x00, 0xEE]);
+        let mut emulator = emulator_with_rom(&rom);
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.program_counter(), 0x300);
+        assert_eq!(emulator.stack_pointer(), 1);
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.program_counter(), 0x202);
+        assert_eq!(emulator.stack_pointer(), 0);
+    }
+
+    #[test]
+    fn fx30_points_at_big_font_digits() {
+        for digit in 0..=9u8 {
+            // V0 = digit；F030
+            let mut emulator = emulator_with_rom(&[0x60, digit, 0xF0, 0x30]);
+            emulator.run_cycles(2).unwrap();
+            let address = emulator.index_register() as usize;
+            assert_eq!(address, BIG_FONTSET_START + digit as usize * 10);
+            let glyph = &BIG_FONTSET[digit as usize * 10..][..10];
+            assert_eq!(emulator.memory[address..address + 10], *glyph);
+        }
+    }
+
+    #[test]
+    fn rpl_flags_round_trip() {
+        // V0 = 1；V1 = 2；V2 = 3；F275；V0～V2清零；F285
+        let mut emulator = emulator_with_rom(&[
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xF2, 0x75, 0x60, 0x00, 0x61, 0x00, 0x62, 0x00,
+            0xF2, 0x85,
+        ]);
+        emulator.run_cycles(7).unwrap();
+        assert_eq!(emulator.registers()[..3], [0, 0, 0]);
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.registers()[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn rpl_flags_clamp_to_eight_registers() {
+        // VF = 0xAA；FF75：只保存V0～V7
+        let mut emulator = emulator_with_rom(&[0x6F, 0xAA, 0x68, 0xBB, 0xFF, 0x75]);
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(emulator.rpl, [0; RPL_SIZE]);
+    }
+
+    #[test]
+    fn skip_nop_0nnn_matches_normal_stepping() {
+        // 三条0NNN之后是V0 = 0x42
+        let rom = [0x01, 0x23, 0x04, 0x56, 0x07, 0x89, 0x60, 0x42];
+        let mut stepped = emulator_with_rom(&rom);
+        for _ in 0..4 {
+            stepped.step_cpu().unwrap();
+        }
+
+        let mut skipped = emulator_with_rom(&rom);
+        skipped.set_skip_nop_0nnn(true);
+        assert_eq!(skipped.step(), Ok(StepResult::Executed(decode(0x01, 0x23))));
+        assert_eq!(skipped.program_counter, 0x206);
+        assert_eq!(skipped.step(), Ok(StepResult::Executed(decode(0x60, 0x42))));
+        assert_eq!(skipped.program_counter, stepped.program_counter);
+        assert_eq!(skipped.registers(), stepped.registers());
+        assert_eq!(skipped.cycles(), stepped.cycles());
+    }
+
+    #[test]
+    fn skip_nop_0nnn_stops_at_system_instructions() {
+        // 0NNN之后是00FF，00FF不是空操作，不能被跳过
+        let mut emulator = emulator_with_rom(&[0x01, 0x23, 0x00, 0xFF, 0x12, 0x04]);
+        emulator.set_skip_nop_0nnn(true);
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.program_counter(), 0x202);
+        emulator.step_cpu().unwrap();
+        assert!(emulator.hires());
+    }
+
+    #[test]
+    fn run_until_waits_for_register() {
+        // DT=0xFF；循环V0 += 1
+        let mut emulator = emulator_with_rom(&[0x6F, 0xFF, 0xFF, 0x15, 0x70, 0x01, 0x12, 0x04]);
+        assert_eq!(
+            emulator.run_until(|emulator| emulator.registers()[0] == 10, 100),
+            Ok(true)
+        );
+        assert_eq!(emulator.registers()[0], 10);
+        assert_eq!(
+            emulator.run_until(|emulator| emulator.registers()[0] == 0, 10),
+            Ok(false)
+        );
+        // 只执行指令，不更新定时器
+        assert_eq!(emulator.timers().0, 0xFF);
+
+        emulator.run_cycles(4).unwrap();
+        assert_eq!(emulator.registers()[0], 17);
+        assert_eq!(emulator.timers().0, 0xFF);
+    }
+
+    #[test]
+    fn run_frame_on_infinite_loop_stays_put() {
+        // V0 = 3；DT = V0；原地循环
+        let mut emulator = emulator_with_rom(&[0x60, 0x03, 0xF0, 0x15, 0x12, 0x04]);
+        emulator.run_frame(2).unwrap();
+        let state = emulator.state();
+        for _ in 0..5 {
+            emulator.run_frame(10).unwrap();
+        }
+        assert_eq!(emulator.program_counter(), 0x204);
+        assert_eq!(emulator.registers(), &state.registers);
+        assert_eq!(emulator.stack_pointer(), 0);
+        assert_eq!(emulator.timers(), (0, 0));
+    }
+
+    #[test]
+    fn step_with_diff_describes_8xy4() {
+        // V0 = 0xFF；V1 = 0x02；V0 += V1
+        let mut emulator = emulator_with_rom(&[0x60, 0xFF, 0x61, 0x02, 0x80, 0x14]);
+        emulator.run_cycles(2).unwrap();
+        let (opcode, diff) = emulator.step_with_diff().unwrap();
+        assert_eq!(opcode, 0x8014);
+        assert_eq!(
+            diff,
+            ["V0: 0xFF → 0x01", "VF: 0x00 → 0x01", "PC: 0x204 → 0x206",]
+        );
+    }
+
+    #[test]
+    fn fx33_stores_decimal_digits() {
+        for (value, digits) in [(0, [0, 0, 0]), (255, [2, 5, 5]), (100, [1, 0, 0])] {
+            // V0 = value；I = 0x300；F033
+            let mut emulator = emulator_with_rom(&[0x60, value, 0xA3, 0x00, 0xF0, 0x33]);
+            emulator.memory[0x300..0x303].fill(0xEE);
+            emulator.run_cycles(3).unwrap();
+            assert_eq!(emulator.memory[0x300..0x303], digits, "{}", value);
+        }
+    }
+
+    #[test]
+    fn stack_limit_allows_deeper_nesting() {
+        // 0x200：调用0x200自身
+        let mut emulator = emulator_with_rom(&[0x22, 0x00]);
+        emulator.set_stack_limit(32);
+        emulator.run_cycles(20).unwrap();
+        assert_eq!(emulator.stack_pointer(), 20);
+        emulator.run_cycles(12).unwrap();
+        assert_eq!(emulator.stack_pointer(), 32);
+        assert_eq!(emulator.step_cpu(), Err(Chip8Error::StackOverflow(0x200)));
+
+        emulator.set_stack_limit(1000);
+        assert_eq!(emulator.stack_limit, MAX_STACK_LIMIT);
+    }
+
+    #[test]
+    fn fx55_wraps_writes_past_end_of_memory() {
+        // V0=1, V1=2, V2=3, I=0xFFF, F255
+        let mut emulator =
+            emulator_with_rom(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xAF, 0xFF, 0xF2, 0x55]);
+        for _ in 0..5 {
+            emulator.step_cpu().unwrap();
+        }
+        assert_eq!(emulator.memory[0xFFF], 1);
+        assert_eq!(emulator.memory[0x000..0x002], [2, 3]);
+    }
+
+    #[test]
+    fn unknown_opcode_is_an_error() {
+        let mut emulator = emulator_with_rom(&[0x50, 0x11]);
+        assert_eq!(emulator.step_cpu(), Err(Chip8Error::UnknownOpcode(0x5011)));
+    }
+
+    #[test]
+    fn seventeen_nested_calls_overflow() {
+        // 每一层调用下一条指令：0x200调用0x202，0x202调用0x204……
+        let rom: Vec<u8> = (0..17u16)
+            .flat_map(|i| (0x2000 | (0x202 + i * 2)).to_be_bytes())
+            .collect();
+        let mut emulator = emulator_with_rom(&rom);
+        emulator.run_cycles(16).unwrap();
+        assert_eq!(emulator.step_cpu(), Err(Chip8Error::StackOverflow(0x220)));
+    }
+
+    #[test]
+    fn bare_return_underflows() {
+        let mut emulator = emulator_with_rom(&[0x60, 0x01, 0x00, 0xEE]);
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.step_cpu(), Err(Chip8Error::StackUnderflow(0x202)));
+    }
+
+    #[test]
+    fn shift_by_register_quirk() {
+        let quirks = Quirks {
+            shift_by_register: true,
+            ..Quirks::default()
+        };
+        // V0 = 0x31；V1 = 3；V0 >>= V1
+        let right = run_with_quirks(quirks, &[0x60, 0x31, 0x61, 0x03, 0x80, 0x16], 3);
+        assert_eq!((right.registers()[0], right.registers()[0xF]), (0x06, 0));
+        // V0 = 0x31；V1 = 3；V0 <<= V1
+        let left = run_with_quirks(quirks, &[0x60, 0x31, 0x61, 0x03, 0x80, 0x1E], 3);
+        assert_eq!((left.registers()[0], left.registers()[0xF]), (0x88, 1));
+        // V1 = 8只取低3位，即不移位
+        let none = run_with_quirks(quirks, &[0x60, 0x31, 0x61, 0x08, 0x80, 0x16], 3);
+        assert_eq!((none.registers()[0], none.registers()[0xF]), (0x31, 0));
+    }
+
+    #[test]
+    fn cycles_count_executed_instructions() {
+        let mut emulator = emulator_with_rom(&[0x70, 0x01, 0x12, 0x00]);
+        emulator.add_breakpoint(0x202);
+        for _ in 0..7 {
+            emulator.step().unwrap();
+        }
+        // 停在断点上的那次step没有执行指令
+        assert_eq!(emulator.cycles(), 5);
+        emulator.reset_cycles();
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(emulator.cycles(), 3);
+    }
+
+    #[test]
+    fn load_rom_bytes_clears_previous_rom() {
+        let mut emulator = emulator_with_rom(&[0x11; 8]);
+        emulator.load_rom_bytes(&[0x22, 0x22]).unwrap();
+        assert_eq!(emulator.memory[0x200..0x202], [0x22, 0x22]);
+        assert!(emulator.memory[0x202..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn index_register_wraps_instead_of_overflowing() {
+        // V0=0xFF; 循环执行F01E，I会多次越过0xFFF
+        let mut emulator = emulator_with_rom(&[0x60, 0xFF, 0xF0, 0x1E, 0x12, 0x02]);
+        for _ in 0..1000 {
+            emulator.step_cpu().unwrap();
+            assert!(emulator.index_register() <= ADDRESS_MASK);
+        }
+
+        // 开启index_increment_on_store时，FX55/FX65之后的I也会回绕
+        let mut emulator = emulator_with_rom(&[0xAF, 0xFE, 0xF3, 0x65]);
+        emulator.set_quirks(Quirks {
+            index_increment_on_store: true,
+            ..emulator.quirks()
+        });
+        emulator.step_cpu().unwrap();
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.index_register(), 0x002);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn load_state_rejects_other_versions() {
+        let mut emulator = Emulator::with_seed(0);
+        let mut data = emulator.save_state();
+        data[4..6].copy_from_slice(&(SAVE_STATE_VERSION + 1).to_le_bytes());
+        let error = emulator.load_state(&data).unwrap_err();
+        assert!(error.to_string().contains("存档版本不匹配"), "{}", error);
+
+        assert!(emulator.load_state(b"not a save state").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn load_state_rejects_invalid_state() {
+        let mut target = emulator_with_rom(&[0x12, 0x00]);
+
+        let mut state = Emulator::with_seed(0);
+        state.program_counter = 0xFFFF;
+        assert!(target.load_state(&state.save_state()).is_err());
+
+        let mut state = Emulator::with_seed(0);
+        state.index_register = 0x1000;
+        assert!(target.load_state(&state.save_state()).is_err());
+
+        let mut state = Emulator::with_seed(0);
+        state.rom_len = MEMORY_SIZE;
+        assert!(target.load_state(&state.save_state()).is_err());
+
+        let mut state = Emulator::with_seed(0);
+        state.stack = vec![0x202; STACK_SIZE + 1];
+        assert!(target.load_state(&state.save_state()).is_err());
+
+        // 失败的加载不会改变当前状态
+        assert_eq!(target.program_counter(), PROGRAM_START);
+        target.step_cpu().unwrap();
+    }
+
+    #[test]
+    fn misaligned_jump_is_an_error_when_enforced() {
+        // 1203跳转到奇数地址
+        let mut emulator = emulator_with_rom(&[0x12, 0x03]);
+        emulator.set_enforce_alignment(true);
+        assert_eq!(emulator.step_cpu(), Err(Chip8Error::MisalignedJump(0x203)));
+        assert_eq!(emulator.program_counter(), 0x202);
+
+        // 2NNN出错时不会压栈，BNNN同样会检查
+        let mut emulator = emulator_with_rom(&[0x22, 0x05, 0x60, 0x01, 0xB2, 0x00]);
+        emulator.set_enforce_alignment(true);
+        assert_eq!(emulator.step_cpu(), Err(Chip8Error::MisalignedJump(0x205)));
+        assert_eq!(emulator.stack_pointer(), 0);
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.step_cpu(), Err(Chip8Error::MisalignedJump(0x201)));
+
+        // 默认不检查对齐
+        let mut emulator = emulator_with_rom(&[0x12, 0x03]);
+        emulator.step_cpu().unwrap();
+        assert_eq!(emulator.program_counter(), 0x203);
+    }
+
+    #[test]
+    fn skip_nop_0nnn_stops_at_breakpoint() {
+        let mut emulator = emulator_with_rom(&[0x01, 0x23, 0x04, 0x56, 0x07, 0x89, 0x60, 0x42]);
+        emulator.set_skip_nop_0nnn(true);
+        emulator.add_breakpoint(0x204);
+        emulator.step().unwrap();
+        assert_eq!(emulator.program_counter, 0x204);
+        assert_eq!(emulator.step(), Ok(StepResult::Breakpoint(0x204)));
+    }
+
+    #[test]
+    fn emulator_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Emulator>();
     }
 }
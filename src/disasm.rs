@@ -0,0 +1,176 @@
+use crate::analysis::{loadable, opcode_at};
+use crate::cpu::{decode, PROGRAM_START};
+
+/// 将一个操作码反汇编为助记符，例如`0x632A`为`LD V3, 0x2A`，`0xD015`为`DRW V0, V1, 5`。
+/// 助记符参考 http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#3.1 ，无法识别的操作码输出为`DW 0xNNNN`
+pub fn disassemble_opcode(opcode: u16) -> String {
+    let op = decode((opcode >> 8) as u8, opcode as u8);
+    let (x, y, n) = (op.second, op.third, op.fourth);
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    // 与process_opcode保持相同的匹配顺序
+    match (op.first, op.second, op.third, op.fourth) {
+        (0, 0, 0xE, 0) => "CLS".to_string(),
+        (0, 0, 0xE, 0xE) => "RET".to_string(),
+        (0, 0, 0xC, _) => format!("SCD {}", n),
+        (0, 0, 0xF, 0xB) => "SCR".to_string(),
+        (0, 0, 0xF, 0xC) => "SCL".to_string(),
+        (0, 0, 0xF, 0xE) => "LOW".to_string(),
+        (0, 0, 0xF, 0xF) => "HIGH".to_string(),
+        (0, _, _, _) => format!("SYS 0x{:03X}", nnn),
+        (1, _, _, _) => format!("JP 0x{:03X}", nnn),
+        (2, _, _, _) => format!("CALL 0x{:03X}", nnn),
+        (3, _, _, _) => format!("SE V{:X}, 0x{:02X}", x, nn),
+        (4, _, _, _) => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        (5, _, _, 0) => format!("SE V{:X}, V{:X}", x, y),
+        (6, _, _, _) => format!("LD V{:X}, 0x{:02X}", x, nn),
+        (7, _, _, _) => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        (8, _, _, 0) => format!("LD V{:X}, V{:X}", x, y),
+        (8, _, _, 1) => format!("OR V{:X}, V{:X}", x, y),
+        (8, _, _, 2) => format!("AND V{:X}, V{:X}", x, y),
+        (8, _, _, 3) => format!("XOR V{:X}, V{:X}", x, y),
+        (8, _, _, 4) => format!("ADD V{:X}, V{:X}", x, y),
+        (8, _, _, 5) => format!("SUB V{:X}, V{:X}", x, y),
+        (8, _, _, 6) => format!("SHR V{:X}, V{:X}", x, y),
+        (8, _, _, 7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (9, _, _, 0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _, _) => format!("LD I, 0x{:03X}", nnn),
+        (0xB, _, _, _) => format!("JP V0, 0x{:03X}", nnn),
+        (0xC, _, _, _) => format!("RND V{:X}, 0x{:02X}", x, nn),
+        (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 1) => format!("SKNP V{:X}", x),
+        (0xF, _, 0, 7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 1, 5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 1, 8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 2, 9) => format!("LD F, V{:X}", x),
+        (0xF, _, 3, 0) => format!("LD HF, V{:X}", x),
+        (0xF, _, 3, 3) => format!("LD B, V{:X}", x),
+        (0xF, _, 5, 5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 6, 5) => format!("LD V{:X}, [I]", x),
+        (0xF, _, 7, 5) => format!("LD R, V{:X}", x),
+        (0xF, _, 8, 5) => format!("LD V{:X}, R", x),
+        _ => format!("DW 0x{:04X}", opcode),
+    }
+}
+
+/// 反汇编整个rom，返回(地址, 助记符)，地址从0x200开始。
+/// 末尾不足两个字节的部分输出为`DB 0xNN`，超出4K内存的部分不会被反汇编
+pub fn disassemble_rom(rom: &[u8]) -> Vec<(u16, String)> {
+    let rom = loadable(rom);
+    (0..rom.len())
+        .step_by(2)
+        .map(|offset| {
+            let address = PROGRAM_START + offset as u16;
+            let mnemonic = match opcode_at(rom, address) {
+                Some(opcode) => disassemble_opcode(opcode),
+                None => format!("DB 0x{:02X}", rom[offset]),
+            };
+            (address, mnemonic)
+        })
+        .collect()
+}
+
+/// 操作码的分类，用于反汇编视图中的语法高亮
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeCategory {
+    Flow,       // 跳转、调用、返回和条件跳过
+    Arithmetic, // 寄存器赋值、运算和随机数
+    Memory,     // 索引寄存器和内存读写
+    Display,    // 清屏和绘制
+    Input,      // 键盘
+    Timer,      // 延迟定时器和声音定时器
+    Unknown,
+}
+
+/// 获取操作码的分类
+pub fn opcode_category(opcode: u16) -> OpcodeCategory {
+    let op = decode((opcode >> 8) as u8, opcode as u8);
+    match (op.first, op.second, op.third, op.fourth) {
+        (0, 0, 0xE, 0) | (0, 0, 0xC, _) => OpcodeCategory::Display,
+        (0, 0, 0xF, 0xB) | (0, 0, 0xF, 0xC) | (0, 0, 0xF, 0xE) | (0, 0, 0xF, 0xF) => {
+            OpcodeCategory::Display
+        }
+        (0, _, _, _) | (1, _, _, _) | (2, _, _, _) => OpcodeCategory::Flow,
+        (3, _, _, _) | (4, _, _, _) | (5, _, _, 0) | (9, _, _, 0) => OpcodeCategory::Flow,
+        (0xB, _, _, _) => OpcodeCategory::Flow,
+        (6, _, _, _) | (7, _, _, _) | (0xC, _, _, _) => OpcodeCategory::Arithmetic,
+        (8, _, _, 0..=7) | (8, _, _, 0xE) => OpcodeCategory::Arithmetic,
+        (0xA, _, _, _) => OpcodeCategory::Memory,
+        (0xD, _, _, _) => OpcodeCategory::Display,
+        (0xE, _, 9, 0xE) | (0xE, _, 0xA, 1) | (0xF, _, 0, 0xA) => OpcodeCategory::Input,
+        (0xF, _, 0, 7) | (0xF, _, 1, 5) | (0xF, _, 1, 8) => OpcodeCategory::Timer,
+        (0xF, _, 1, 0xE) | (0xF, _, 2, 9) | (0xF, _, 3, 0) | (0xF, _, 3, 3) => {
+            OpcodeCategory::Memory
+        }
+        (0xF, _, 5, 5) | (0xF, _, 6, 5) | (0xF, _, 7, 5) | (0xF, _, 8, 5) => OpcodeCategory::Memory,
+        _ => OpcodeCategory::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassembles_each_nibble_group() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x00EE, "RET"),
+            (0x0123, "SYS 0x123"),
+            (0x1234, "JP 0x234"),
+            (0x2345, "CALL 0x345"),
+            (0x3A2B, "SE VA, 0x2B"),
+            (0x4B3C, "SNE VB, 0x3C"),
+            (0x5120, "SE V1, V2"),
+            (0x632A, "LD V3, 0x2A"),
+            (0x7401, "ADD V4, 0x01"),
+            (0x8564, "ADD V5, V6"),
+            (0x856E, "SHL V5, V6"),
+            (0x9780, "SNE V7, V8"),
+            (0xA123, "LD I, 0x123"),
+            (0xB200, "JP V0, 0x200"),
+            (0xC90F, "RND V9, 0x0F"),
+            (0xD015, "DRW V0, V1, 5"),
+            (0xE19E, "SKP V1"),
+            (0xE2A1, "SKNP V2"),
+            (0xF30A, "LD V3, K"),
+            (0xF433, "LD B, V4"),
+        ];
+        for (opcode, mnemonic) in cases {
+            assert_eq!(disassemble_opcode(opcode), mnemonic, "{:#06X}", opcode);
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes_are_data_words() {
+        assert_eq!(disassemble_opcode(0x5011), "DW 0x5011");
+        assert_eq!(disassemble_opcode(0xE1FF), "DW 0xE1FF");
+        assert_eq!(disassemble_opcode(0xF1FF), "DW 0xF1FF");
+    }
+
+    #[test]
+    fn disassemble_rom_pairs_addresses_with_mnemonics() {
+        let rom = [0xA2, 0x2A, 0x00, 0xE0, 0xD1, 0x25, 0x12];
+        assert_eq!(
+            disassemble_rom(&rom),
+            [
+                (0x200, "LD I, 0x22A".to_string()),
+                (0x202, "CLS".to_string()),
+                (0x204, "DRW V1, V2, 5".to_string()),
+                (0x206, "DB 0x12".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn disassemble_rom_stops_at_end_of_memory() {
+        let listing = disassemble_rom(&[0; 70_000]);
+        assert_eq!(listing.len(), 1792);
+        assert_eq!(listing.last().unwrap().0, 0xFFE);
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt;
+
+/// 执行指令时可能出现的错误，由调用方决定停止运行还是跳过这条指令继续执行
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// 无法识别的操作码，pc已经指向下一条指令，再次执行即可跳过
+    UnknownOpcode(u16),
+    /// 2NNN调用时堆栈已满，参数为调用指令的地址
+    StackOverflow(u16),
+    /// 00EE返回时堆栈为空，参数为返回指令的地址
+    StackUnderflow(u16),
+    /// 开启对齐检查时跳转到奇数地址，参数为跳转的目标地址
+    MisalignedJump(u16),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode(opcode) => write!(f, "无法识别的操作码 {:#06X}", opcode),
+            Chip8Error::StackOverflow(address) => {
+                write!(f, "堆栈溢出: 在 {:#05X} 处调用时堆栈已满", address)
+            }
+            Chip8Error::StackUnderflow(address) => {
+                write!(f, "堆栈下溢: 在 {:#05X} 处从空栈返回", address)
+            }
+            Chip8Error::MisalignedJump(address) => {
+                write!(f, "跳转到未对齐的地址: {:#05X}", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use crate::Emulator;
 
 pub enum KeyState {
@@ -5,32 +7,230 @@ pub enum KeyState {
     Down,
 }
 
-pub fn process_key(emulator: &mut Emulator, key: char, state: KeyState) {
+/// chip8原始的4x4键盘布局（COSMAC VIP），每个元素是对应位置按键的索引
+pub const KEYPAD_LAYOUT: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// 键盘字符到chip8键盘索引的映射，默认使用QWERTY布局，
+/// AZERTY、Dvorak等布局的用户可以通过`insert`重新绑定按键
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap(HashMap<char, usize>);
+
+impl KeyMap {
+    /// 创建一个没有任何绑定的映射
+    pub fn empty() -> Self {
+        KeyMap(HashMap::new())
+    }
+
+    /// 将字符绑定到chip8键盘的索引，索引超过0xF时panic
+    pub fn insert(mut self, key: char, index: u8) -> Self {
+        assert!(index <= 0x0F, "chip8键盘的索引超出范围: {:#04X}", index);
+        self.0.insert(key, index as usize);
+        self
+    }
+
+    /// 将键盘字符映射为chip8键盘的索引，未映射的按键返回None
+    pub fn get(&self, key: char) -> Option<usize> {
+        self.0.get(&key).copied()
+    }
+}
+
+impl Default for KeyMap {
+    /// QWERTY键盘左侧的4x4区域对应chip8的键盘布局
+    fn default() -> Self {
+        let keys = [
+            ['1', '2', '3', '4'],
+            ['q', 'w', 'e', 'r'],
+            ['a', 's', 'd', 'f'],
+            ['z', 'x', 'c', 'v'],
+        ];
+        let mut map = KeyMap::empty();
+        for (row, layout_row) in keys.iter().zip(KEYPAD_LAYOUT.iter()) {
+            for (&key, &index) in row.iter().zip(layout_row.iter()) {
+                map = map.insert(key, index as u8);
+            }
+        }
+        map
+    }
+}
+
+/// 将按键事件写入模拟器的键盘状态。
+/// 按键映射到了chip8键盘时返回true，否则返回false，前端可以把未处理的按键交给其他处理逻辑
+pub fn process_key(emulator: &mut Emulator, keymap: &KeyMap, key: char, state: KeyState) -> bool {
     let key_value = match state {
         KeyState::Up => false,
         KeyState::Down => true,
     };
 
-    match key {
-        '1' => emulator.keypad[0x1] = key_value,
-        '2' => emulator.keypad[0x2] = key_value,
-        '3' => emulator.keypad[0x3] = key_value,
-        '4' => emulator.keypad[0xC] = key_value,
-
-        'q' => emulator.keypad[0x4] = key_value,
-        'w' => emulator.keypad[0x5] = key_value,
-        'e' => emulator.keypad[0x6] = key_value,
-        'r' => emulator.keypad[0xD] = key_value,
-
-        'a' => emulator.keypad[0x7] = key_value,
-        's' => emulator.keypad[0x8] = key_value,
-        'd' => emulator.keypad[0x9] = key_value,
-        'f' => emulator.keypad[0xE] = key_value,
-
-        'z' => emulator.keypad[0xA] = key_value,
-        'x' => emulator.keypad[0x0] = key_value,
-        'c' => emulator.keypad[0xB] = key_value,
-        'v' => emulator.keypad[0xF] = key_value,
-        _ => {}
+    match keymap.get(key) {
+        Some(index) => {
+            emulator.keypad[index] = key_value;
+            true
+        }
+        None => false,
+    }
+}
+
+/// 按住按键时的自动连发，delay和interval的单位都是帧。
+/// 按键按住超过delay帧后开始连发，interval是一次连发的完整周期：松开1帧，再按下interval-1帧，
+/// 这样轮询EX9E的菜单类rom可以把一直按住的按键识别为多次按下。
+pub struct KeyRepeat {
+    delay: u32,
+    interval: u32,
+    held_frames: [u32; 16], // 每个按键已经按住的帧数
+    released: [bool; 16],   // 本帧被自动连发临时松开的按键
+}
+
+impl KeyRepeat {
+    /// 周期中至少要有松开和按下各一帧，interval小于2时panic
+    pub fn new(delay: u32, interval: u32) -> Self {
+        assert!(interval >= 2, "连发周期至少为2帧，实际为 {}", interval);
+        KeyRepeat {
+            delay,
+            interval,
+            held_frames: [0; 16],
+            released: [false; 16],
+        }
+    }
+
+    /// 使用自动连发时，按键事件需要通过这里传入，避免真实的松开事件被连发重新按下。
+    /// 返回值与`process_key`相同
+    pub fn process_key(
+        &mut self,
+        emulator: &mut Emulator,
+        keymap: &KeyMap,
+        key: char,
+        state: KeyState,
+    ) -> bool {
+        if let (Some(index), KeyState::Up) = (keymap.get(key), &state) {
+            self.released[index] = false;
+            self.held_frames[index] = 0;
+        }
+        process_key(emulator, keymap, key, state)
+    }
+
+    /// 松开所有按键并清除连发状态，使用自动连发的前端应在窗口失去焦点时调用它，
+    /// 而不是`Emulator::release_all_keys`
+    pub fn release_all(&mut self, emulator: &mut Emulator) {
+        self.held_frames = [0; 16];
+        self.released = [false; 16];
+        emulator.release_all_keys();
+    }
+
+    /// 每帧调用一次，更新按住按键的连发状态
+    pub fn update(&mut self, emulator: &mut Emulator) {
+        for key in 0..self.held_frames.len() {
+            if self.released[key] {
+                // 上一帧被连发松开的按键，重新按下，这一帧计入周期中按下的部分
+                self.released[key] = false;
+                emulator.keypad[key] = true;
+                self.held_frames[key] += 1;
+                continue;
+            } else if !emulator.keypad[key] {
+                self.held_frames[key] = 0;
+                continue;
+            }
+
+            self.held_frames[key] += 1;
+            let held = self.held_frames[key];
+            if held > self.delay && (held - self.delay).is_multiple_of(self.interval) {
+                self.released[key] = true;
+                emulator.keypad[key] = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按住按键后连续更新`frames`帧，返回每帧更新后按键的状态
+    fn hold(repeat: &mut KeyRepeat, key: char, frames: usize) -> Vec<bool> {
+        let mut emulator = Emulator::with_seed(0);
+        let keymap = KeyMap::default();
+        let index = keymap.get(key).unwrap();
+        repeat.process_key(&mut emulator, &keymap, key, KeyState::Down);
+        (0..frames)
+            .map(|_| {
+                repeat.update(&mut emulator);
+                emulator.keypad[index]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn key_repeat_toggles_on_interval() {
+        let mut repeat = KeyRepeat::new(3, 2);
+        assert_eq!(
+            hold(&mut repeat, 'q', 9),
+            [true, true, true, true, false, true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn custom_keymap_sets_remapped_slot() {
+        let mut emulator = Emulator::with_seed(0);
+        let keymap = KeyMap::empty().insert('a', 0x4).insert('k', 0xC);
+        assert!(process_key(&mut emulator, &keymap, 'a', KeyState::Down));
+        assert!(process_key(&mut emulator, &keymap, 'k', KeyState::Down));
+        assert!(!process_key(&mut emulator, &keymap, 'q', KeyState::Down));
+        assert!(emulator.keypad[0x4]);
+        assert!(emulator.keypad[0xC]);
+        assert_eq!(
+            emulator.keypad.iter().filter(|&&pressed| pressed).count(),
+            2
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "chip8键盘的索引超出范围")]
+    fn keymap_rejects_index_above_0xf() {
+        KeyMap::empty().insert('k', 0x1C);
+    }
+
+    #[test]
+    fn process_key_reports_whether_key_was_mapped() {
+        let mut emulator = Emulator::with_seed(0);
+        let keymap = KeyMap::default();
+        assert!(!process_key(&mut emulator, &keymap, 'p', KeyState::Down));
+        assert_eq!(emulator.keypad, [false; 16]);
+        assert!(process_key(&mut emulator, &keymap, 'q', KeyState::Down));
+        assert!(emulator.keypad[0x4]);
+    }
+
+    #[test]
+    fn key_repeat_interval_is_full_period() {
+        let mut repeat = KeyRepeat::new(0, 3);
+        assert_eq!(
+            hold(&mut repeat, 'q', 6),
+            [true, true, false, true, true, false]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "连发周期至少为2帧")]
+    fn key_repeat_rejects_interval_below_two() {
+        KeyRepeat::new(0, 1);
+    }
+
+    #[test]
+    fn release_all_clears_repeat_state() {
+        let mut emulator = Emulator::with_seed(0);
+        let keymap = KeyMap::default();
+        let mut repeat = KeyRepeat::new(0, 2);
+        repeat.process_key(&mut emulator, &keymap, 'q', KeyState::Down);
+        // 第二帧按键被连发临时松开
+        repeat.update(&mut emulator);
+        repeat.update(&mut emulator);
+        repeat.release_all(&mut emulator);
+        for _ in 0..5 {
+            repeat.update(&mut emulator);
+            assert!(!emulator.keypad[0x4]);
+        }
     }
 }
@@ -1,5 +1,24 @@
+mod analysis;
+mod audio;
 mod cpu;
+mod disasm;
+mod error;
 mod input;
-pub use cpu::Emulator;
-pub use cpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
-pub use input::{process_key, KeyState};
+mod quirks;
+#[cfg(feature = "serde")]
+mod serde_arrays;
+mod terminal;
+pub use analysis::{
+    call_graph, classify_regions, detect_resolution, find_subroutines, DisplayMode, Region,
+    RegionKind, RomLint,
+};
+pub use audio::{AudioSink, NullAudio};
+pub use cpu::{CpuState, Emulator, OpCode, StepResult};
+pub use cpu::{
+    DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_HEIGHT, HIRES_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+pub use disasm::{disassemble_opcode, disassemble_rom, opcode_category, OpcodeCategory};
+pub use error::Chip8Error;
+pub use input::{process_key, KeyMap, KeyRepeat, KeyState, KEYPAD_LAYOUT};
+pub use quirks::Quirks;
+pub use terminal::{render_terminal, render_terminal_to, TerminalRenderer};
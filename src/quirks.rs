@@ -0,0 +1,27 @@
+/// CHIP-8在不同解释器之间存在一些有歧义的行为，这里用开关来选择具体的实现。
+/// 默认值对应现代解释器（以及大多数现代rom）的行为
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    /// 8XY6/8XYE先将VY复制到VX再移位（原始COSMAC VIP），否则直接对VX移位
+    pub shift_uses_vy: bool,
+    /// FX55/FX65执行后I增加X+1（原始COSMAC VIP），否则I保持不变
+    pub index_increment_on_store: bool,
+    /// BNNN按照BXNN解释，跳转到VX + NNN（SUPER-CHIP），否则跳转到V0 + NNN
+    pub bnnn_uses_vx: bool,
+    /// 8XY6/8XYE将VX移动`VY & 0x07`位而不是1位，VF为最后移出的一位（移动0位时为0），
+    /// 开启时忽略`shift_uses_vy`。这不是任何标准解释器的行为，只用于教学演示
+    pub shift_by_register: bool,
+}
+
+impl Quirks {
+    /// 原始COSMAC VIP解释器的行为
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            index_increment_on_store: true,
+            bnnn_uses_vx: false,
+            shift_by_register: false,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! serde只为长度不超过32的数组实现了序列化，这里为内存和屏幕这类大数组提供辅助函数，
+//! 通过`#[serde(with = "...")]`使用
+
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+/// `[u8; N]`，序列化为字节串
+pub mod bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        array: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(array)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"a byte array of the expected size"))
+    }
+}
+
+/// `[[u8; W]; H]`，按行展开后序列化为字节串
+pub mod bytes_2d {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const W: usize, const H: usize>(
+        array: &[[u8; W]; H],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(array.as_flattened())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const W: usize, const H: usize>(
+        deserializer: D,
+    ) -> Result<[[u8; W]; H], D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        if bytes.len() != W * H {
+            return Err(D::Error::invalid_length(
+                bytes.len(),
+                &"a byte array of the expected size",
+            ));
+        }
+        let mut array = [[0; W]; H];
+        for (row, chunk) in array.iter_mut().zip(bytes.chunks_exact(W)) {
+            row.copy_from_slice(chunk);
+        }
+        Ok(array)
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::{self, Write};
+
+use crate::Emulator;
+
+/// 清空终端并将光标移到左上角的ANSI转义序列
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// 一个终端字符显示上下两个像素，返回对应的半块字符
+fn half_block(top: bool, bottom: bool) -> char {
+    match (top, bottom) {
+        (true, true) => '█',
+        (true, false) => '▀',
+        (false, true) => '▄',
+        (false, false) => ' ',
+    }
+}
+
+/// 将屏幕转换为终端字符，返回(列数, 按行排列的字符)
+fn cells(emulator: &Emulator) -> (usize, Vec<char>) {
+    let (width, height) = emulator.dimensions();
+    let mut cells = Vec::with_capacity(width * height / 2);
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            cells.push(half_block(emulator.pixel(x, y), emulator.pixel(x, y + 1)));
+        }
+    }
+    (width, cells)
+}
+
+/// 清屏后完整地绘制所有字符
+fn full_frame(width: usize, cells: &[char]) -> String {
+    let mut frame = String::from(CLEAR_SCREEN);
+    for row in cells.chunks(width) {
+        frame.extend(row);
+        frame.push('\n');
+    }
+    frame
+}
+
+/// 将模拟器当前的屏幕绘制到标准输出，不需要SDL等外部窗口就能看到画面。
+/// 每帧都会清屏并将光标移回左上角，每个字符用半块字符显示上下两个像素，
+/// 64x32的屏幕占用64列16行（高分辨率时为128列32行）
+pub fn render_terminal(emulator: &Emulator) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    render_terminal_to(emulator, &mut out)?;
+    out.flush()
+}
+
+/// 与`render_terminal`相同，但写入到任意的输出中
+pub fn render_terminal_to<W: Write>(emulator: &Emulator, out: &mut W) -> io::Result<()> {
+    let (width, cells) = cells(emulator);
+    out.write_all(full_frame(width, &cells).as_bytes())
+}
+
+/// 增量的终端渲染器，记住上一帧绘制的字符，之后只输出发生变化的字符，
+/// 避免整屏重绘带来的闪烁，也减少了SSH等慢速连接上传输的数据量
+#[derive(Debug, Default)]
+pub struct TerminalRenderer {
+    width: usize,
+    previous: Option<Vec<char>>, // 上一帧绘制的字符，None时下一帧完整重绘
+}
+
+impl TerminalRenderer {
+    pub fn new() -> Self {
+        TerminalRenderer::default()
+    }
+
+    /// 让下一帧完整重绘，终端大小改变或者屏幕被其他输出弄乱之后调用
+    pub fn invalidate(&mut self) {
+        self.previous = None;
+    }
+
+    /// 绘制一帧。第一帧、调用`invalidate`之后或者分辨率切换时完整重绘，
+    /// 其他时候只对变化的字符输出光标移动和字符
+    pub fn render<W: Write>(&mut self, emulator: &Emulator, out: &mut W) -> io::Result<()> {
+        let (width, cells) = cells(emulator);
+        let frame = match &self.previous {
+            Some(previous) if self.width == width && previous.len() == cells.len() => {
+                let mut frame = String::new();
+                for (index, (old, new)) in previous.iter().zip(cells.iter()).enumerate() {
+                    if old != new {
+                        // ANSI的行列从1开始
+                        let (row, column) = (index / width + 1, index % width + 1);
+                        frame.push_str(&format!("\x1b[{};{}H{}", row, column, new));
+                    }
+                }
+                frame
+            }
+            _ => full_frame(width, &cells),
+        };
+        self.width = width;
+        self.previous = Some(cells);
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在(x, y)绘制精灵后的模拟器
+    fn emulator_with_sprite(x: u8, y: u8, sprite: &[u8]) -> Emulator {
+        // V0 = x；V1 = y；I = 0x20A；D01N；原地循环
+        let n = sprite.len() as u8;
+        let mut rom = vec![0x60, x, 0x61, y, 0xA2, 0x0A, 0xD0, 0x10 | n, 0x12, 0x08];
+        rom.extend_from_slice(sprite);
+        let mut emulator = Emulator::with_seed(0);
+        emulator.load_rom_bytes(&rom).unwrap();
+        emulator.run_cycles(4).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn render_terminal_uses_half_blocks() {
+        let emulator = emulator_with_sprite(0, 0, &[0xC0, 0x80, 0x00, 0x40]);
+        let mut out = Vec::new();
+        render_terminal_to(&emulator, &mut out).unwrap();
+        let output = String::from_utf8(out).unwrap();
+
+        let frame = output.strip_prefix(CLEAR_SCREEN).unwrap();
+        let rows: Vec<&str> = frame.lines().collect();
+        assert_eq!(rows.len(), 16);
+        assert!(rows.iter().all(|row| row.chars().count() == 64));
+        assert!(rows[0].starts_with("█▀ "));
+        assert!(rows[1].starts_with(" ▄ "));
+        assert!(rows[2..].iter().all(|row| row.trim().is_empty()));
+    }
+
+    #[test]
+    fn renderer_only_redraws_changed_cells() {
+        let mut renderer = TerminalRenderer::new();
+        let mut out = Vec::new();
+        renderer
+            .render(&emulator_with_sprite(0, 0, &[0x80]), &mut out)
+            .unwrap();
+        assert!(out.starts_with(CLEAR_SCREEN.as_bytes()));
+
+        // (5, 3)多点亮一个像素，对应第2行第6列的字符
+        let emulator = emulator_with_sprite(0, 0, &[0x80, 0x00, 0x00, 0x04]);
+        out.clear();
+        renderer.render(&emulator, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\x1b[2;6H▄");
+
+        renderer.invalidate();
+        let mut out = Vec::new();
+        renderer.render(&emulator, &mut out).unwrap();
+        assert!(out.starts_with(CLEAR_SCREEN.as_bytes()));
+    }
+}